@@ -11,6 +11,7 @@ pub enum Argument {
     ImageArg(Img<f32>),
 }
 
+#[allow(clippy::needless_borrow)]
 pub fn parse_args(argls: &[String]) -> Result<Vec<Argument>, ExecError> {
     let mut args: Vec<Argument> = Vec::new();
     
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::cell::Cell;
 use std::cell::RefCell;
+use rand::Rng;
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 
@@ -26,24 +28,40 @@ pub enum StackValue {
     Integer(i32),
     Float(f32),
     Size(i32, i32),
+    Point(f32, f32),
     Color(Pix<f32>),
     Image(Rc<Img<f32>>),
     Proc(Rc<Vec<ScriptToken>>),
     Array(Rc<Vec<StackValue>>),
+    Dict(Rc<HashMap<String, StackValue>>),
+    Matrix([f32; 6]),
 }
 
 pub struct ExecContext {
     stack: Vec<StackValue>,
     heap: HashMap<String, StackValue>,
+    scopes: Vec<HashMap<String, StackValue>>,
     rng: Rc<RefCell<SmallRng>>,
+    noiseseed: Rc<Cell<u64>>,
+    linearlight: bool,
 }
 
 impl ExecContext {
-    pub fn new() -> ExecContext {
+    pub fn new(seed: Option<u64>) -> ExecContext {
+        let mut rng = match seed {
+            Some(val) => SmallRng::seed_from_u64(val),
+            None => SmallRng::from_entropy(),
+        };
+        // The noise field stays fixed for the whole run (until srand),
+        // so that noise builtins can be sampled point by point.
+        let noiseseed: u64 = rng.gen();
         ExecContext {
             stack: Vec::new(),
             heap: HashMap::new(),
-            rng: Rc::new(RefCell::new(SmallRng::from_entropy())),
+            scopes: Vec::new(),
+            rng: Rc::new(RefCell::new(rng)),
+            noiseseed: Rc::new(Cell::new(noiseseed)),
+            linearlight: false,
         }
     }
 
@@ -51,14 +69,46 @@ impl ExecContext {
         ExecContext {
             stack: Vec::new(),
             heap: self.heap.clone(),
+            scopes: self.scopes.clone(),
             rng: Rc::clone(&self.rng),
+            noiseseed: Rc::clone(&self.noiseseed),
+            linearlight: self.linearlight,
         }
     }
 
+    pub fn set_linearlight(&mut self, val: bool) {
+        // When set, resampling and blurs convert to linear light and
+        // back, rather than blending gamma-encoded values.
+        self.linearlight = val;
+    }
+
     pub fn stack(&self) -> &[StackValue] {
         &self.stack
     }
 
+    pub fn lookup(&self, name: &str) -> Option<&StackValue> {
+        // Dict scopes (from begin) are searched innermost first, then
+        // the heap.
+        for scope in self.scopes.iter().rev() {
+            if let Some(val) = scope.get(name) {
+                return Some(val);
+            }
+        }
+        self.heap.get(name)
+    }
+
+    pub fn store(&mut self, name: &str, val: StackValue) {
+        // Overwrite the innermost definition if there is one, so that
+        // lookup sees the new value.
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(entry) = scope.get_mut(name) {
+                *entry = val;
+                return;
+            }
+        }
+        self.heap.insert(name.to_string(), val);
+    }
+
     pub fn unloadargs(&mut self, outs: &Vec<String>) -> Result<(), ExecError> {
         for out in outs {
             let img = self.pop_img("output")?;
@@ -75,12 +125,14 @@ impl ExecContext {
         self.execute(&mut execstack)
     }
 
+    #[allow(clippy::needless_borrow)]
     pub fn execute_proc(&mut self, proc: &Rc<Vec<ScriptToken>>, execstack: &mut LendStackIter<ScriptToken>, inval: StackValue) -> Result<(), ExecError> {
         execstack.push(&proc);
         self.push(inval);
         self.execute(execstack)
     }
     
+    #[allow(clippy::needless_borrow)]
     pub fn execute_proc_2(&mut self, proc: &Rc<Vec<ScriptToken>>, execstack: &mut LendStackIter<ScriptToken>, inval1: StackValue, inval2: StackValue) -> Result<(), ExecError> {
         execstack.push(&proc);
         self.push(inval1);
@@ -106,12 +158,15 @@ impl ExecContext {
                 ScriptToken::Size(valx, valy) => {
                     self.push(StackValue::Size(*valx, *valy));
                 },
+                ScriptToken::Point(valx, valy) => {
+                    self.push(StackValue::Point(*valx, *valy));
+                },
                 ScriptToken::Color(valr, valg, valb) => {
                     let pix: Pix<f32> = Pix::new(*valr as f32, *valg as f32, *valb as f32);
                     self.push(StackValue::Color(pix));
                 },
                 ScriptToken::Name(val) => {
-                    if let Some(heapval) = self.heap.get(val) {
+                    if let Some(heapval) = self.lookup(val) {
                         if let StackValue::Proc(proc) = heapval {
                             execstack.push(proc);
                         }
@@ -130,7 +185,20 @@ impl ExecContext {
                 ScriptToken::StoreTo(val) => {
                     let stackval = self.stack.pop()
                         .ok_or_else(|| ExecError::new("stack underflow") )?;
-                    self.heap.insert(val.to_string(), stackval);
+                    self.store(val, stackval);
+                }
+                ScriptToken::Bind(names) => {
+                    // Parameters are bound in push order, so the last
+                    // name gets the top of the stack.
+                    if self.stack.len() < names.len() {
+                        let missing = &names[self.stack.len()];
+                        let msg = format!("missing parameter {} ({} of {})", missing, self.stack.len()+1, names.len());
+                        return Err(ExecError::new(&msg));
+                    }
+                    let vals = self.stack.split_off(self.stack.len() - names.len());
+                    for (name, stackval) in std::iter::zip(names, vals) {
+                        self.store(name, stackval);
+                    }
                 }
                 other => {
                     let msg = format!("unknown token: {:?}", other);
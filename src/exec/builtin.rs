@@ -1,11 +1,34 @@
 use std::rc::Rc;
+use std::collections::HashMap;
 use rand::Rng;
+use rand::SeedableRng;
 use rand::rngs::SmallRng;
 use rand::seq::SliceRandom;
 
 use crate::img::pixel::Pix;
 use crate::img::Img;
 use crate::img::ppmio;
+use crate::img::random::hash_gauss;
+use crate::img::noise;
+use crate::img::noise::NoiseKind;
+use crate::img::noise::Fractal;
+use crate::img::gradient::Gradient;
+use crate::img::draw;
+use crate::img::draw::FillRule;
+use crate::img::draw::BlendMode;
+use crate::img::draw::Paint;
+use crate::img::text::Font;
+use crate::img::tone;
+use crate::img::channels;
+use crate::img::channels::ChannelSource;
+use crate::img::edge::GradientKernel;
+use crate::img::morph::StructElem;
+use crate::img::quantize::Quantizer;
+use crate::img::quantize::DitherMethod;
+use crate::img::warp;
+use crate::img::warp::Sampler;
+use crate::img::tone::Levels;
+use crate::img::tone::ToneCurve;
 use crate::script::ScriptToken;
 use crate::script::parse::load_script_file;
 use crate::exec::StackValue;
@@ -15,22 +38,44 @@ use crate::exec::except::ExecError;
 use crate::exec::util::elementwise;
 use crate::exec::util::elementwise_bool;
 use crate::exec::util::elementwise_2;
+use crate::exec::util::elementwise_int_2;
 use crate::exec::util::elementwise_bool_2;
 use crate::exec::util::sigmoid;
+use crate::exec::util::stringify;
 
 #[derive(Debug, Clone)]
 pub enum BuiltInSymbol {
     Mark,
     Array,
+    DictMark,
+    Dict,
+    Get,
+    Put,
+    Known,
+    Keys,
+    Begin,
+    End,
     Dup,
     Pop,
     Swap,
+    Def,
     Eval,
     If,
     IfElse,
     Cond,
     Break,
     Random,
+    SRand,
+    Gauss,
+    RandColor,
+    Shuffle,
+    RandImage,
+    GaussImage,
+    Perlin,
+    Simplex,
+    Worley,
+    Fbm,
+    Turbulence,
     Split,
     Size,
     Color,
@@ -38,16 +83,46 @@ pub enum BuiltInSymbol {
     Write,
     Read,
     Run,
+    Format,
+    ToString,
+    Parse,
+    Substr,
+    Length,
     IsNan,
     Pi,
+    E,
+    Tau,
     Sin,
     Cos,
     Tan,
     ASin,
     ACos,
     ATan,
+    ATan2,
+    Sqrt,
+    Pow,
+    Exp,
+    Ln,
+    Log10,
+    Abs,
+    Sign,
+    Floor,
+    Ceil,
+    Round,
+    Fract,
+    Min,
+    Max,
+    Clamp,
+    Lerp,
+    Step,
+    SmoothStep,
     OpOr,
     OpAnd,
+    OpXor,
+    OpNot,
+    OpEq,
+    OpNE,
+    Approx,
     OpLT,
     OpGT,
     OpLTE,
@@ -58,10 +133,18 @@ pub enum BuiltInSymbol {
     OpMul,
     OpDiv,
     OpMod,
+    IDiv,
+    Int,
+    Float,
     Hypot,
     Shade,
     Sigmoid,
     Average,
+    Histogram,
+    MinMax,
+    StdDev,
+    Median,
+    Percentile,
     Map,
     MapVal,
     Project,
@@ -70,11 +153,84 @@ pub enum BuiltInSymbol {
     At,
     NAt,
     Contrast,
+    Normalize,
+    AutoLevels,
+    Equalize,
+    Clahe,
+    Gamma,
+    Levels,
+    Curves,
+    Lut,
+    ToHSV,
+    FromHSV,
+    ToHSL,
+    FromHSL,
+    ToLab,
+    FromLab,
+    ToYCC,
+    FromYCC,
+    ToLinear,
+    ToSRGB,
+    LinearLight,
+    SplitChannels,
+    MergeChannels,
+    Swizzle,
+    Sobel,
+    Scharr,
+    Laplacian,
+    Canny,
+    Erode,
+    Dilate,
+    Open,
+    Close,
+    TopHat,
+    MorphGradient,
+    MedianFilter,
+    Bilateral,
+    NLMeans,
+    GaussBlur,
+    Unsharp,
+    HighPass,
+    Flatten,
+    Threshold,
+    Otsu,
+    AdaptiveThreshold,
+    Dither,
+    Quantize,
+    MedianCut,
+    KMeans,
+    WrapBytes,
+    Fft,
+    Ifft,
+    FftFilter,
+    Homography,
+    Warp,
+    Translate,
+    Scale,
+    Rotate,
+    Skew,
+    Invert,
+    ConcatMatrix,
+    Transform,
+    Point,
+    Dot,
     Shift,
     HalfShift,
     TileBy,
     Concat,
     Diamond,
+    LinearGradient,
+    RadialGradient,
+    ConicGradient,
+    CornerGradient,
+    Line,
+    Polyline,
+    Rect,
+    Circle,
+    Ellipse,
+    Polygon,
+    Bezier,
+    Text,
     Holify,
     TaxiBlur,
     Seamless,
@@ -85,15 +241,35 @@ impl ExecContext {
         match tok {
             "[" => Some(BuiltInSymbol::Mark),
             "]" => Some(BuiltInSymbol::Array),
+            "<<" => Some(BuiltInSymbol::DictMark),
+            ">>" => Some(BuiltInSymbol::Dict),
+            "get" => Some(BuiltInSymbol::Get),
+            "put" => Some(BuiltInSymbol::Put),
+            "known" => Some(BuiltInSymbol::Known),
+            "keys" => Some(BuiltInSymbol::Keys),
+            "begin" => Some(BuiltInSymbol::Begin),
+            "end" => Some(BuiltInSymbol::End),
             "dup" => Some(BuiltInSymbol::Dup),
             "pop" => Some(BuiltInSymbol::Pop),
             "swap" => Some(BuiltInSymbol::Swap),
+            "def" => Some(BuiltInSymbol::Def),
             "eval" => Some(BuiltInSymbol::Eval),
             "if" => Some(BuiltInSymbol::If),
             "ifelse" => Some(BuiltInSymbol::IfElse),
             "cond" => Some(BuiltInSymbol::Cond),
             "break" => Some(BuiltInSymbol::Break),
             "random" => Some(BuiltInSymbol::Random),
+            "srand" => Some(BuiltInSymbol::SRand),
+            "gauss" => Some(BuiltInSymbol::Gauss),
+            "randcolor" => Some(BuiltInSymbol::RandColor),
+            "shuffle" => Some(BuiltInSymbol::Shuffle),
+            "randimage" => Some(BuiltInSymbol::RandImage),
+            "gaussimage" => Some(BuiltInSymbol::GaussImage),
+            "perlin" => Some(BuiltInSymbol::Perlin),
+            "simplex" => Some(BuiltInSymbol::Simplex),
+            "worley" => Some(BuiltInSymbol::Worley),
+            "fbm" => Some(BuiltInSymbol::Fbm),
+            "turbulence" => Some(BuiltInSymbol::Turbulence),
             "split" => Some(BuiltInSymbol::Split),
             "size" => Some(BuiltInSymbol::Size),
             "color" => Some(BuiltInSymbol::Color),
@@ -101,16 +277,46 @@ impl ExecContext {
             "write" => Some(BuiltInSymbol::Write),
             "read" => Some(BuiltInSymbol::Read),
             "run" => Some(BuiltInSymbol::Run),
+            "format" => Some(BuiltInSymbol::Format),
+            "tostring" => Some(BuiltInSymbol::ToString),
+            "parse" => Some(BuiltInSymbol::Parse),
+            "substr" => Some(BuiltInSymbol::Substr),
+            "length" => Some(BuiltInSymbol::Length),
             "isnan" => Some(BuiltInSymbol::IsNan),
             "pi" => Some(BuiltInSymbol::Pi),
+            "e" => Some(BuiltInSymbol::E),
+            "tau" => Some(BuiltInSymbol::Tau),
             "sin" => Some(BuiltInSymbol::Sin),
             "cos" => Some(BuiltInSymbol::Cos),
             "tan" => Some(BuiltInSymbol::Tan),
             "asin" => Some(BuiltInSymbol::ASin),
             "acos" => Some(BuiltInSymbol::ACos),
             "atan" => Some(BuiltInSymbol::ATan),
+            "atan2" => Some(BuiltInSymbol::ATan2),
+            "sqrt" => Some(BuiltInSymbol::Sqrt),
+            "pow" => Some(BuiltInSymbol::Pow),
+            "exp" => Some(BuiltInSymbol::Exp),
+            "ln" => Some(BuiltInSymbol::Ln),
+            "log10" => Some(BuiltInSymbol::Log10),
+            "abs" => Some(BuiltInSymbol::Abs),
+            "sign" => Some(BuiltInSymbol::Sign),
+            "floor" => Some(BuiltInSymbol::Floor),
+            "ceil" => Some(BuiltInSymbol::Ceil),
+            "round" => Some(BuiltInSymbol::Round),
+            "fract" => Some(BuiltInSymbol::Fract),
+            "min" => Some(BuiltInSymbol::Min),
+            "max" => Some(BuiltInSymbol::Max),
+            "clamp" => Some(BuiltInSymbol::Clamp),
+            "lerp" => Some(BuiltInSymbol::Lerp),
+            "step" => Some(BuiltInSymbol::Step),
+            "smoothstep" => Some(BuiltInSymbol::SmoothStep),
             "or" => Some(BuiltInSymbol::OpOr),
             "and" => Some(BuiltInSymbol::OpAnd),
+            "xor" => Some(BuiltInSymbol::OpXor),
+            "not" => Some(BuiltInSymbol::OpNot),
+            "==" => Some(BuiltInSymbol::OpEq),
+            "!=" => Some(BuiltInSymbol::OpNE),
+            "approx" => Some(BuiltInSymbol::Approx),
             "<" => Some(BuiltInSymbol::OpLT),
             ">" => Some(BuiltInSymbol::OpGT),
             "<=" => Some(BuiltInSymbol::OpLTE),
@@ -121,10 +327,18 @@ impl ExecContext {
             "*" => Some(BuiltInSymbol::OpMul),
             "/" => Some(BuiltInSymbol::OpDiv),
             "%" => Some(BuiltInSymbol::OpMod),
+            "idiv" => Some(BuiltInSymbol::IDiv),
+            "int" => Some(BuiltInSymbol::Int),
+            "float" => Some(BuiltInSymbol::Float),
             "hypot" => Some(BuiltInSymbol::Hypot),
             "shade" => Some(BuiltInSymbol::Shade),
             "sigmoid" => Some(BuiltInSymbol::Sigmoid),
             "average" => Some(BuiltInSymbol::Average),
+            "histogram" => Some(BuiltInSymbol::Histogram),
+            "minmax" => Some(BuiltInSymbol::MinMax),
+            "stddev" => Some(BuiltInSymbol::StdDev),
+            "median" => Some(BuiltInSymbol::Median),
+            "percentile" => Some(BuiltInSymbol::Percentile),
             "map" => Some(BuiltInSymbol::Map),
             "mapval" => Some(BuiltInSymbol::MapVal),
             "project" => Some(BuiltInSymbol::Project),
@@ -133,11 +347,84 @@ impl ExecContext {
             "nat" => Some(BuiltInSymbol::NAt),
             "interpolate" => Some(BuiltInSymbol::Interpolate),
             "contrast" => Some(BuiltInSymbol::Contrast),
+            "normalize" => Some(BuiltInSymbol::Normalize),
+            "autolevels" => Some(BuiltInSymbol::AutoLevels),
+            "equalize" => Some(BuiltInSymbol::Equalize),
+            "clahe" => Some(BuiltInSymbol::Clahe),
+            "gamma" => Some(BuiltInSymbol::Gamma),
+            "levels" => Some(BuiltInSymbol::Levels),
+            "curves" => Some(BuiltInSymbol::Curves),
+            "lut" => Some(BuiltInSymbol::Lut),
+            "tohsv" => Some(BuiltInSymbol::ToHSV),
+            "fromhsv" => Some(BuiltInSymbol::FromHSV),
+            "tohsl" => Some(BuiltInSymbol::ToHSL),
+            "fromhsl" => Some(BuiltInSymbol::FromHSL),
+            "tolab" => Some(BuiltInSymbol::ToLab),
+            "fromlab" => Some(BuiltInSymbol::FromLab),
+            "toycc" => Some(BuiltInSymbol::ToYCC),
+            "fromycc" => Some(BuiltInSymbol::FromYCC),
+            "tolinear" => Some(BuiltInSymbol::ToLinear),
+            "tosrgb" => Some(BuiltInSymbol::ToSRGB),
+            "linearlight" => Some(BuiltInSymbol::LinearLight),
+            "splitchannels" => Some(BuiltInSymbol::SplitChannels),
+            "mergechannels" => Some(BuiltInSymbol::MergeChannels),
+            "swizzle" => Some(BuiltInSymbol::Swizzle),
+            "sobel" => Some(BuiltInSymbol::Sobel),
+            "scharr" => Some(BuiltInSymbol::Scharr),
+            "laplacian" => Some(BuiltInSymbol::Laplacian),
+            "canny" => Some(BuiltInSymbol::Canny),
+            "erode" => Some(BuiltInSymbol::Erode),
+            "dilate" => Some(BuiltInSymbol::Dilate),
+            "open" => Some(BuiltInSymbol::Open),
+            "close" => Some(BuiltInSymbol::Close),
+            "tophat" => Some(BuiltInSymbol::TopHat),
+            "gradient" => Some(BuiltInSymbol::MorphGradient),
+            "medianfilter" => Some(BuiltInSymbol::MedianFilter),
+            "bilateral" => Some(BuiltInSymbol::Bilateral),
+            "nlmeans" => Some(BuiltInSymbol::NLMeans),
+            "gaussblur" => Some(BuiltInSymbol::GaussBlur),
+            "unsharp" => Some(BuiltInSymbol::Unsharp),
+            "highpass" => Some(BuiltInSymbol::HighPass),
+            "flatten" => Some(BuiltInSymbol::Flatten),
+            "threshold" => Some(BuiltInSymbol::Threshold),
+            "otsu" => Some(BuiltInSymbol::Otsu),
+            "adaptivethreshold" => Some(BuiltInSymbol::AdaptiveThreshold),
+            "dither" => Some(BuiltInSymbol::Dither),
+            "quantize" => Some(BuiltInSymbol::Quantize),
+            "mediancut" => Some(BuiltInSymbol::MedianCut),
+            "kmeans" => Some(BuiltInSymbol::KMeans),
+            "wrapbytes" => Some(BuiltInSymbol::WrapBytes),
+            "fft" => Some(BuiltInSymbol::Fft),
+            "ifft" => Some(BuiltInSymbol::Ifft),
+            "fftfilter" => Some(BuiltInSymbol::FftFilter),
+            "homography" => Some(BuiltInSymbol::Homography),
+            "warp" => Some(BuiltInSymbol::Warp),
+            "translate" => Some(BuiltInSymbol::Translate),
+            "scale" => Some(BuiltInSymbol::Scale),
+            "rotate" => Some(BuiltInSymbol::Rotate),
+            "skew" => Some(BuiltInSymbol::Skew),
+            "invert" => Some(BuiltInSymbol::Invert),
+            "concatmatrix" => Some(BuiltInSymbol::ConcatMatrix),
+            "transform" => Some(BuiltInSymbol::Transform),
+            "point" => Some(BuiltInSymbol::Point),
+            "dot" => Some(BuiltInSymbol::Dot),
             "shift" => Some(BuiltInSymbol::Shift),
             "halfshift" => Some(BuiltInSymbol::HalfShift),
             "tileby" => Some(BuiltInSymbol::TileBy),
             "concat" => Some(BuiltInSymbol::Concat),
             "diamond" => Some(BuiltInSymbol::Diamond),
+            "lineargradient" => Some(BuiltInSymbol::LinearGradient),
+            "radialgradient" => Some(BuiltInSymbol::RadialGradient),
+            "conicgradient" => Some(BuiltInSymbol::ConicGradient),
+            "cornergradient" => Some(BuiltInSymbol::CornerGradient),
+            "line" => Some(BuiltInSymbol::Line),
+            "polyline" => Some(BuiltInSymbol::Polyline),
+            "rect" => Some(BuiltInSymbol::Rect),
+            "circle" => Some(BuiltInSymbol::Circle),
+            "ellipse" => Some(BuiltInSymbol::Ellipse),
+            "polygon" => Some(BuiltInSymbol::Polygon),
+            "bezier" => Some(BuiltInSymbol::Bezier),
+            "text" => Some(BuiltInSymbol::Text),
             "holify" => Some(BuiltInSymbol::Holify),
             "taxiblur" => Some(BuiltInSymbol::TaxiBlur),
             "seamless" => Some(BuiltInSymbol::Seamless),
@@ -153,6 +440,7 @@ impl ExecContext {
             },
             
             BuiltInSymbol::Array => {
+                #[allow(clippy::match_like_matches_macro)]
                 let pos = self.stack.iter().rposition(|val| match val {
                     StackValue::Mark => true,
                     _ => false,
@@ -163,6 +451,100 @@ impl ExecContext {
                 self.push_array(tail);
             },
             
+            BuiltInSymbol::DictMark => {
+                self.push(StackValue::Mark);
+            },
+            
+            BuiltInSymbol::Dict => {
+                // << KEY VAL KEY VAL ... >>
+                let pos = self.stack.iter().rposition(|val| matches!(val, StackValue::Mark))
+                    .ok_or_else(|| ExecError::new("no dict mark on stack") )?;
+                let tail = self.stack.split_off(pos+1);
+                let _ = self.pop("dict")?;
+                if !tail.len().is_multiple_of(2) {
+                    let msg = format!("dict needs key-value pairs: {} values", tail.len());
+                    return Err(ExecError::new(&msg));
+                }
+                let mut dict: HashMap<String, StackValue> = HashMap::new();
+                let mut iter = tail.into_iter();
+                while let (Some(key), Some(val)) = (iter.next(), iter.next()) {
+                    if let StackValue::String(name) = key {
+                        dict.insert(name, val);
+                    }
+                    else {
+                        let msg = format!("dict key must be str: {:?}", key);
+                        return Err(ExecError::new(&msg));
+                    }
+                }
+                self.push_dict(dict);
+            },
+            
+            BuiltInSymbol::Get => {
+                // DICT STR get, ARRAY INT get
+                let key = self.pop("get")?;
+                let container = self.pop("get")?;
+                match (container, key) {
+                    (StackValue::Dict(dict), StackValue::String(name)) => {
+                        let val = dict.get(&name)
+                            .ok_or_else(|| {
+                                let msg = format!("get key not in dict: {:?}", name);
+                                ExecError::new(&msg)
+                            })?;
+                        self.push(val.clone());
+                    },
+                    (StackValue::Array(arr), StackValue::Integer(index)) => {
+                        if index < 0 || index as usize >= arr.len() {
+                            let msg = format!("get index out of range: {index}");
+                            return Err(ExecError::new(&msg));
+                        }
+                        self.push(arr[index as usize].clone());
+                    },
+                    (xcontainer, xkey) => {
+                        let msg = format!("cannot get: {:?} {:?}", xcontainer, xkey);
+                        return Err(ExecError::new(&msg));
+                    },
+                }
+            },
+            
+            BuiltInSymbol::Put => {
+                // DICT STR VAL put (returns a new dict)
+                let val = self.pop("put")?;
+                let name = self.pop_str("put")?;
+                let dict = self.pop_dict("put")?;
+                let mut res = (*dict).clone();
+                res.insert(name, val);
+                self.push_dict(res);
+            },
+            
+            BuiltInSymbol::Known => {
+                // DICT STR known
+                let name = self.pop_str("known")?;
+                let dict = self.pop_dict("known")?;
+                self.push_int(dict.contains_key(&name) as i32);
+            },
+            
+            BuiltInSymbol::Keys => {
+                // DICT keys
+                let dict = self.pop_dict("keys")?;
+                let mut names: Vec<&String> = dict.keys().collect();
+                names.sort();
+                let res: Vec<StackValue> = names.into_iter()
+                    .map(|name| StackValue::String(name.clone()))
+                    .collect();
+                self.push_array(res);
+            },
+            
+            BuiltInSymbol::Begin => {
+                // DICT begin
+                let dict = self.pop_dict("begin")?;
+                self.scopes.push((*dict).clone());
+            },
+            
+            BuiltInSymbol::End => {
+                self.scopes.pop()
+                    .ok_or_else(|| ExecError::new("end without begin") )?;
+            },
+            
             BuiltInSymbol::Dup => {
                 let stackval = self.stack.last()
                     .ok_or_else(|| ExecError::new("stack underflow") )?;
@@ -180,6 +562,13 @@ impl ExecContext {
                 self.push(val2);
             },
 
+            BuiltInSymbol::Def => {
+                // STR VAL def (/name VAL def)
+                let val = self.pop("def")?;
+                let name = self.pop_str("def")?;
+                self.store(&name, val);
+            },
+
             BuiltInSymbol::Eval => {
                 let stackval = self.pop("eval")?;
                 match stackval {
@@ -187,7 +576,7 @@ impl ExecContext {
                         execstack.push(&proc);
                     },
                     StackValue::String(val) => {
-                        if let Some(heapval) = self.heap.get(&val) {
+                        if let Some(heapval) = self.lookup(&val) {
                             if let StackValue::Proc(proc) = heapval {
                                 execstack.push(proc);
                             }
@@ -270,6 +659,7 @@ impl ExecContext {
                 }
                 if let Some(val) = res {
                     if let StackValue::Proc(proc) = val {
+                        #[allow(clippy::needless_borrow)]
                         execstack.push(&proc);
                     }
                     else {
@@ -321,14 +711,147 @@ impl ExecContext {
                 }
             },
             
+            BuiltInSymbol::SRand => {
+                // INT srand
+                let seed = self.pop_int("srand")?;
+                let mut rng = SmallRng::seed_from_u64(seed as u64);
+                self.noiseseed.set(rng.gen());
+                *self.rng.borrow_mut() = rng;
+            },
+            
+            BuiltInSymbol::Gauss => {
+                // MEAN STDDEV gauss
+                let stddev = self.pop_as_float("gauss")?;
+                let mean = self.pop_as_float("gauss")?;
+                let seed: u64 = self.rng.borrow_mut().gen();
+                self.push_float(mean + stddev * hash_gauss(seed, 0, 0, 0));
+            },
+            
+            BuiltInSymbol::RandColor => {
+                let pix: Pix<f32> = {
+                    let mut rng = self.rng.borrow_mut();
+                    Pix::new(rng.gen_range(0.0..255.0), rng.gen_range(0.0..255.0), rng.gen_range(0.0..255.0))
+                };
+                self.push_color(pix);
+            },
+            
+            BuiltInSymbol::Shuffle => {
+                // ARRAY shuffle
+                let arr = self.pop_array("shuffle")?;
+                let mut res = (*arr).clone();
+                {
+                    let mut rng = self.rng.borrow_mut();
+                    res.shuffle::<SmallRng>(&mut rng);
+                }
+                self.push_array(res);
+            },
+            
+            BuiltInSymbol::RandImage => {
+                // SIZE randimage, SIZE "grey" randimage
+                let mode = self.pop_opt_str();
+                let (width, height) = self.pop_as_size("randimage")?;
+                if width <= 0 || height <= 0 {
+                    let msg = format!("randimage size must be positive: {width}x{height}");
+                    return Err(ExecError::new(&msg));
+                }
+                let grey = mode.as_deref() == Some("grey");
+                let seed: u64 = self.rng.borrow_mut().gen();
+                let res = Img::random_uniform(width as usize, height as usize, seed, grey);
+                self.push_img(res);
+            },
+            
+            BuiltInSymbol::GaussImage => {
+                // SIZE MEAN STDDEV gaussimage, SIZE MEAN STDDEV "grey" gaussimage
+                let mode = self.pop_opt_str();
+                let stddev = self.pop_as_float("gaussimage")?;
+                let mean = self.pop_as_float("gaussimage")?;
+                let (width, height) = self.pop_as_size("gaussimage")?;
+                if width <= 0 || height <= 0 {
+                    let msg = format!("gaussimage size must be positive: {width}x{height}");
+                    return Err(ExecError::new(&msg));
+                }
+                let grey = mode.as_deref() == Some("grey");
+                let seed: u64 = self.rng.borrow_mut().gen();
+                let res = Img::random_gauss(width as usize, height as usize, seed, mean, stddev, grey);
+                self.push_img(res);
+            },
+            
+            BuiltInSymbol::Perlin => {
+                // X Y perlin, SIZE CELLS perlin, SIZE CELLS "tile" perlin
+                let tile = self.pop_noise_tile("perlin")?;
+                self.execute_noise("perlin", NoiseKind::Perlin, None, tile)?;
+            },
+            
+            BuiltInSymbol::Simplex => {
+                // X Y simplex, SIZE CELLS simplex, SIZE CELLS "tile" simplex
+                let tile = self.pop_noise_tile("simplex")?;
+                self.execute_noise("simplex", NoiseKind::Simplex, None, tile)?;
+            },
+            
+            BuiltInSymbol::Worley => {
+                // X Y worley, SIZE CELLS worley, SIZE CELLS "tile" worley
+                let tile = self.pop_noise_tile("worley")?;
+                self.execute_noise("worley", NoiseKind::Worley, None, tile)?;
+            },
+            
+            BuiltInSymbol::Fbm | BuiltInSymbol::Turbulence => {
+                // X Y OCTAVES LACUNARITY GAIN fbm
+                // SIZE CELLS OCTAVES LACUNARITY GAIN fbm
+                // (optional "perlin", "simplex" or "worley" basis, then
+                // optional "tile")
+                let label = if let BuiltInSymbol::Fbm = sym { "fbm" } else { "turbulence" };
+                let mut mode = self.pop_opt_str();
+                let tile = mode.as_deref() == Some("tile");
+                if tile {
+                    mode = self.pop_opt_str();
+                }
+                let kind = match mode.as_deref() {
+                    None | Some("perlin") => NoiseKind::Perlin,
+                    Some("simplex") => NoiseKind::Simplex,
+                    Some("worley") => NoiseKind::Worley,
+                    Some(other) => {
+                        let msg = format!("{} mode not known: {:?}", label, other);
+                        return Err(ExecError::new(&msg));
+                    },
+                };
+                let gain = self.pop_as_float(label)?;
+                let lacunarity = self.pop_as_float(label)?;
+                let octaves = self.pop_int(label)?;
+                if !(1..=16).contains(&octaves) {
+                    let msg = format!("{} octaves must be 1 to 16: {}", label, octaves);
+                    return Err(ExecError::new(&msg));
+                }
+                let frac = Fractal {
+                    octaves,
+                    lacunarity,
+                    gain,
+                    turbulence: matches!(sym, BuiltInSymbol::Turbulence),
+                };
+                self.execute_noise(label, kind, Some(frac), tile)?;
+            },
+            
             BuiltInSymbol::Split => {
-                // COLOR split, SIZE split
+                // COLOR split, SIZE split, POINT split, STR STR split
                 let stackval = self.pop("split")?;
                 match stackval {
+                    StackValue::String(delim) => {
+                        let val = self.pop_str("split")?;
+                        if delim.is_empty() {
+                            return Err(ExecError::new("split delimiter must be nonempty"));
+                        }
+                        let res: Vec<StackValue> = val.split(delim.as_str())
+                            .map(|part| StackValue::String(part.to_string()))
+                            .collect();
+                        self.push_array(res);
+                    }
                     StackValue::Size(xval, yval) => {
                         self.push_int(xval);
                         self.push_int(yval);
                     }
+                    StackValue::Point(xval, yval) => {
+                        self.push_float(xval);
+                        self.push_float(yval);
+                    }
                     StackValue::Color(pix) => {
                         self.push_float(pix.r);
                         self.push_float(pix.g);
@@ -357,6 +880,7 @@ impl ExecContext {
                 // SIZE COLOR image, INT INT COLOR image
                 // SIZE NUM image, INT INT NUM image
                 // SIZE PROC image, INT INT PROC image
+                #[allow(clippy::needless_late_init)]
                 let color: StackValue;
                 
                 let colorval = self.pop("image")?;
@@ -386,6 +910,7 @@ impl ExecContext {
                     return Err(ExecError::new(&msg));
                 }
 
+                #[allow(clippy::needless_late_init)]
                 let img: Img<f32>;
                 match color {
                     StackValue::Color(pix) => {
@@ -429,6 +954,90 @@ impl ExecContext {
                 execstack.push(&script.tokens());
             },
 
+            BuiltInSymbol::Format => {
+                // STR VAL format, STR ARRAY format (one value per {})
+                let arg = self.pop("format")?;
+                let fmt = self.pop_str("format")?;
+                let vals: Vec<StackValue> = match arg {
+                    StackValue::Array(arr) => arr.to_vec(),
+                    other => vec![other],
+                };
+                let pieces: Vec<&str> = fmt.split("{}").collect();
+                let count = pieces.len() - 1;
+                if vals.len() != count {
+                    let msg = format!("format has {} placeholders but {} values", count, vals.len());
+                    return Err(ExecError::new(&msg));
+                }
+                let mut res = String::from(pieces[0]);
+                for (val, piece) in std::iter::zip(&vals, &pieces[1..]) {
+                    res.push_str(&stringify(val));
+                    res.push_str(piece);
+                }
+                self.push_str(res);
+            },
+
+            BuiltInSymbol::ToString => {
+                // VAL tostring
+                let val = self.pop("tostring")?;
+                self.push_str(stringify(&val));
+            },
+
+            BuiltInSymbol::Parse => {
+                // STR parse
+                let val = self.pop_str("parse")?;
+                let trimmed = val.trim();
+                if let Ok(ival) = trimmed.parse::<i32>() {
+                    self.push_int(ival);
+                }
+                else if let Ok(fval) = trimmed.parse::<f32>() {
+                    self.push_float(fval);
+                }
+                else {
+                    let msg = format!("cannot parse as number: {:?}", val);
+                    return Err(ExecError::new(&msg));
+                }
+            },
+
+            BuiltInSymbol::Substr => {
+                // STR START LEN substr
+                let len = self.pop_int("substr")?;
+                let start = self.pop_int("substr")?;
+                let val = self.pop_str("substr")?;
+                if start < 0 || len < 0 {
+                    let msg = format!("substr range cannot be negative: {start} {len}");
+                    return Err(ExecError::new(&msg));
+                }
+                // A range running off the end is cut short.
+                let total = val.chars().count() as i32;
+                let start = start.min(total);
+                let end = start.saturating_add(len).min(total);
+                let res: String = val.chars().skip(start as usize).take((end - start) as usize).collect();
+                self.push_str(res);
+            },
+
+            BuiltInSymbol::Length => {
+                // STR length, ARRAY length, DICT length, POINT length
+                let stackval = self.pop("length")?;
+                match stackval {
+                    StackValue::String(val) => {
+                        self.push_int(val.chars().count() as i32);
+                    },
+                    StackValue::Array(arr) => {
+                        self.push_int(arr.len() as i32);
+                    },
+                    StackValue::Dict(dict) => {
+                        self.push_int(dict.len() as i32);
+                    },
+                    StackValue::Point(xval, yval) => {
+                        self.push_float(xval.hypot(yval));
+                    },
+                    _ => {
+                        let msg = format!("cannot length: {:?}", stackval);
+                        return Err(ExecError::new(&msg));
+                    }
+                }
+            },
+
             BuiltInSymbol::IsNan => {
                 let varg = self.pop("isnan")?;
                 let stackval = elementwise_bool(varg, |val| val.is_nan())?;
@@ -439,6 +1048,14 @@ impl ExecContext {
                 self.push_float(std::f32::consts::PI);
             },
             
+            BuiltInSymbol::E => {
+                self.push_float(std::f32::consts::E);
+            },
+            
+            BuiltInSymbol::Tau => {
+                self.push_float(std::f32::consts::TAU);
+            },
+            
             BuiltInSymbol::Sin => {
                 let varg = self.pop("sin")?;
                 let stackval = elementwise(varg, |val| val.sin())?;
@@ -475,18 +1092,190 @@ impl ExecContext {
                 self.push(stackval);
             },
 
+            BuiltInSymbol::ATan2 => {
+                // Y X atan2
+                let varg2 = self.pop("atan2")?;
+                let varg1 = self.pop("atan2")?;
+                let stackval = elementwise_2(varg1, varg2, |vy, vx| vy.atan2(*vx))?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Sqrt => {
+                let varg = self.pop("sqrt")?;
+                let stackval = elementwise(varg, |val| val.sqrt())?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Pow => {
+                // BASE EXP pow
+                let varg2 = self.pop("pow")?;
+                let varg1 = self.pop("pow")?;
+                let stackval = elementwise_2(varg1, varg2, |v1, v2| v1.powf(*v2))?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Exp => {
+                let varg = self.pop("exp")?;
+                let stackval = elementwise(varg, |val| val.exp())?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Ln => {
+                let varg = self.pop("ln")?;
+                let stackval = elementwise(varg, |val| val.ln())?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Log10 => {
+                let varg = self.pop("log10")?;
+                let stackval = elementwise(varg, |val| val.log10())?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Abs => {
+                let varg = self.pop("abs")?;
+                let stackval = elementwise(varg, |val| val.abs())?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Sign => {
+                let varg = self.pop("sign")?;
+                let stackval = elementwise(varg, |val| {
+                    if *val > 0.0 { 1.0 } else if *val < 0.0 { -1.0 } else { 0.0 }
+                })?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Floor => {
+                let varg = self.pop("floor")?;
+                let stackval = elementwise(varg, |val| val.floor())?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Ceil => {
+                let varg = self.pop("ceil")?;
+                let stackval = elementwise(varg, |val| val.ceil())?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Round => {
+                let varg = self.pop("round")?;
+                let stackval = elementwise(varg, |val| val.round())?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Fract => {
+                let varg = self.pop("fract")?;
+                let stackval = elementwise(varg, |val| val - val.floor())?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Min => {
+                let varg2 = self.pop("min")?;
+                let varg1 = self.pop("min")?;
+                let stackval = elementwise_2(varg1, varg2, |v1, v2| v1.min(*v2))?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Max => {
+                let varg2 = self.pop("max")?;
+                let varg1 = self.pop("max")?;
+                let stackval = elementwise_2(varg1, varg2, |v1, v2| v1.max(*v2))?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Clamp => {
+                // VAL LO HI clamp
+                let vhi = self.pop("clamp")?;
+                let vlo = self.pop("clamp")?;
+                let varg = self.pop("clamp")?;
+                let stackval = elementwise_2(varg, vlo, |v1, v2| v1.max(*v2))?;
+                let stackval = elementwise_2(stackval, vhi, |v1, v2| v1.min(*v2))?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Lerp => {
+                // A B T lerp
+                let vfrac = self.pop("lerp")?;
+                let varg2 = self.pop("lerp")?;
+                let varg1 = self.pop("lerp")?;
+                let diff = elementwise_2(varg2, varg1.clone(), |v2, v1| v2-v1)?;
+                let diff = elementwise_2(diff, vfrac, |vd, vt| vd*vt)?;
+                let stackval = elementwise_2(varg1, diff, |v1, vd| v1+vd)?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Step => {
+                // EDGE VAL step
+                let varg2 = self.pop("step")?;
+                let varg1 = self.pop("step")?;
+                let stackval = elementwise_2(varg1, varg2, |vedge, val| if val < vedge {0.0} else {1.0})?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::SmoothStep => {
+                // EDGE0 EDGE1 VAL smoothstep
+                let varg = self.pop("smoothstep")?;
+                let vedge1 = self.pop("smoothstep")?;
+                let vedge0 = self.pop("smoothstep")?;
+                let width = elementwise_2(vedge1, vedge0.clone(), |v1, v0| v1-v0)?;
+                let offset = elementwise_2(varg, vedge0, |val, v0| val-v0)?;
+                let frac = elementwise_2(offset, width, |vo, vw| vo/vw)?;
+                let stackval = elementwise(frac, |val| {
+                    let tval = val.clamp(0.0, 1.0);
+                    tval * tval * (3.0 - 2.0 * tval)
+                })?;
+                self.push(stackval);
+            },
+
             BuiltInSymbol::OpOr => {
-                let flag2 = self.pop_int("or")?;
-                let flag1 = self.pop_int("or")?;
-                let res = flag1 != 0 || flag2 != 0;
-                self.push_int(res as i32);
+                let varg2 = self.pop("or")?;
+                let varg1 = self.pop("or")?;
+                let stackval = elementwise_bool_2(varg1, varg2, |v1, v2| *v1 != 0.0 || *v2 != 0.0)?;
+                self.push(stackval);
             },
 
             BuiltInSymbol::OpAnd => {
-                let flag2 = self.pop_int("and")?;
-                let flag1 = self.pop_int("and")?;
-                let res = flag1 != 0 && flag2 != 0;
-                self.push_int(res as i32);
+                let varg2 = self.pop("and")?;
+                let varg1 = self.pop("and")?;
+                let stackval = elementwise_bool_2(varg1, varg2, |v1, v2| *v1 != 0.0 && *v2 != 0.0)?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::OpXor => {
+                let varg2 = self.pop("xor")?;
+                let varg1 = self.pop("xor")?;
+                let stackval = elementwise_bool_2(varg1, varg2, |v1, v2| (*v1 != 0.0) != (*v2 != 0.0))?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::OpNot => {
+                let varg = self.pop("not")?;
+                let stackval = elementwise_bool(varg, |val| *val == 0.0)?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::OpEq => {
+                let varg2 = self.pop("==")?;
+                let varg1 = self.pop("==")?;
+                let stackval = elementwise_bool_2(varg1, varg2, |v1, v2| v1==v2)?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::OpNE => {
+                let varg2 = self.pop("!=")?;
+                let varg1 = self.pop("!=")?;
+                let stackval = elementwise_bool_2(varg1, varg2, |v1, v2| v1!=v2)?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::Approx => {
+                // VAL1 VAL2 EPSILON approx
+                let epsilon = self.pop_as_float("approx")?;
+                let varg2 = self.pop("approx")?;
+                let varg1 = self.pop("approx")?;
+                let stackval = elementwise_bool_2(varg1, varg2, |v1, v2| (v1-v2).abs() <= epsilon)?;
+                self.push(stackval);
             },
 
             BuiltInSymbol::OpLT => {
@@ -518,7 +1307,13 @@ impl ExecContext {
             },
 
             BuiltInSymbol::OpNeg => {
-                let varg = self.pop("acos")?;
+                let varg = self.pop("neg")?;
+                if let StackValue::Integer(ival) = varg {
+                    let res = ival.checked_neg()
+                        .ok_or_else(|| ExecError::new("integer overflow in neg") )?;
+                    self.push_int(res);
+                    return Ok(());
+                }
                 let stackval = elementwise(varg, |val| -val)?;
                 self.push(stackval);                
             },
@@ -526,21 +1321,21 @@ impl ExecContext {
             BuiltInSymbol::OpAdd => {
                 let varg2 = self.pop("+")?;
                 let varg1 = self.pop("+")?;
-                let stackval = elementwise_2(varg1, varg2, |v1, v2| v1+v2)?;
+                let stackval = elementwise_int_2(varg1, varg2, |i1, i2| i1.checked_add(i2), |v1, v2| v1+v2)?;
                 self.push(stackval);
             },
 
             BuiltInSymbol::OpSub => {
                 let varg2 = self.pop("-")?;
                 let varg1 = self.pop("-")?;
-                let stackval = elementwise_2(varg1, varg2, |v1, v2| v1-v2)?;
+                let stackval = elementwise_int_2(varg1, varg2, |i1, i2| i1.checked_sub(i2), |v1, v2| v1-v2)?;
                 self.push(stackval);
             },
 
             BuiltInSymbol::OpMul => {
                 let varg2 = self.pop("*")?;
                 let varg1 = self.pop("*")?;
-                let stackval = elementwise_2(varg1, varg2, |v1, v2| v1*v2)?;
+                let stackval = elementwise_int_2(varg1, varg2, |i1, i2| i1.checked_mul(i2), |v1, v2| v1*v2)?;
                 self.push(stackval);
             },
 
@@ -554,10 +1349,56 @@ impl ExecContext {
             BuiltInSymbol::OpMod => {
                 let varg2 = self.pop("%")?;
                 let varg1 = self.pop("%")?;
-                let stackval = elementwise_2(varg1, varg2, |v1, v2| v1%v2)?;
+                let stackval = elementwise_int_2(varg1, varg2, |i1, i2| i1.checked_rem(i2), |v1, v2| v1%v2)?;
+                self.push(stackval);
+            },
+
+            BuiltInSymbol::IDiv => {
+                // Truncating division, so that A B idiv B * A B % + equals A.
+                let varg2 = self.pop("idiv")?;
+                let varg1 = self.pop("idiv")?;
+                let stackval = elementwise_int_2(varg1, varg2, |i1, i2| i1.checked_div(i2), |v1, v2| (v1/v2).trunc())?;
                 self.push(stackval);
             },
 
+            BuiltInSymbol::Int => {
+                // NUM int, NUM STR int (trunc, floor, ceil, round)
+                let mode = self.pop_opt_str();
+                let stackval = self.pop("int")?;
+                match stackval {
+                    StackValue::Integer(ival) => {
+                        self.push_int(ival);
+                    },
+                    StackValue::Float(fval) => {
+                        let rval = match mode.as_deref() {
+                            None | Some("trunc") => fval.trunc(),
+                            Some("floor") => fval.floor(),
+                            Some("ceil") => fval.ceil(),
+                            Some("round") => fval.round(),
+                            Some(other) => {
+                                let msg = format!("int mode not known: {:?}", other);
+                                return Err(ExecError::new(&msg));
+                            },
+                        };
+                        if !rval.is_finite() || rval < i32::MIN as f32 || rval >= i32::MAX as f32 {
+                            let msg = format!("int out of range: {fval}");
+                            return Err(ExecError::new(&msg));
+                        }
+                        self.push_int(rval as i32);
+                    },
+                    _ => {
+                        let msg = format!("int needs num: {:?}", stackval);
+                        return Err(ExecError::new(&msg));
+                    },
+                }
+            },
+
+            BuiltInSymbol::Float => {
+                // NUM float
+                let val = self.pop_as_float("float")?;
+                self.push_float(val);
+            },
+
             BuiltInSymbol::Hypot => {
                 let varg2 = self.pop("hypot")?;
                 let varg1 = self.pop("hypot")?;
@@ -595,6 +1436,58 @@ impl ExecContext {
                 self.push_color(pix);
             },
 
+            BuiltInSymbol::Histogram => {
+                // IMG INT histogram: an array of three arrays (R, G, B)
+                // of INT counts each
+                let bins = self.pop_int("histogram")?;
+                let img: Rc<Img<f32>> = self.pop_img("histogram")?;
+                if !(1..=65536).contains(&bins) {
+                    let msg = format!("histogram bins must be 1 to 65536: {}", bins);
+                    return Err(ExecError::new(&msg));
+                }
+                let res: Vec<StackValue> = img.histogram(bins as usize).iter()
+                    .map(|hist| {
+                        let counts = hist.iter().map(|count| StackValue::Integer(*count as i32)).collect();
+                        StackValue::Array(Rc::new(counts))
+                    })
+                    .collect();
+                self.push_array(res);
+            },
+
+            BuiltInSymbol::MinMax => {
+                // IMG minmax: pushes the min color, then the max color
+                let img: Rc<Img<f32>> = self.pop_img("minmax")?;
+                let (lo, hi) = img.minmax();
+                self.push_color(lo);
+                self.push_color(hi);
+            },
+
+            BuiltInSymbol::StdDev => {
+                // IMG stddev
+                let img: Rc<Img<f32>> = self.pop_img("stddev")?;
+                let pix = img.stddev();
+                self.push_color(pix);
+            },
+
+            BuiltInSymbol::Median => {
+                // IMG median: the median color
+                let img: Rc<Img<f32>> = self.pop_img("median")?;
+                let pix = img.percentile(50.0);
+                self.push_color(pix);
+            },
+
+            BuiltInSymbol::Percentile => {
+                // IMG NUM percentile (NUM from 0 to 100)
+                let pct = self.pop_as_float("percentile")?;
+                let img: Rc<Img<f32>> = self.pop_img("percentile")?;
+                if !(0.0..=100.0).contains(&pct) {
+                    let msg = format!("percentile must be 0 to 100: {}", pct);
+                    return Err(ExecError::new(&msg));
+                }
+                let pix = img.percentile(pct);
+                self.push_color(pix);
+            },
+
             BuiltInSymbol::Map => {
                 // IMG PROC map
                 let proc = self.pop_proc("map")?;
@@ -629,21 +1522,44 @@ impl ExecContext {
 
             BuiltInSymbol::Project => {
                 // IMG PROC project
+                // IMG MATRIX project (maps each pixel to where it samples,
+                // like the proc)
                 //### or IMG IMG project?
                 //### get a SIZE in there?
+                if let [.., StackValue::Matrix(_)] = &self.stack[..] {
+                    let mat = self.pop_affine("project")?;
+                    let img: Rc<Img<f32>> = self.pop_img_light("project")?;
+                    let res = img.project_map(|px, py| warp::affine_apply(&mat, px, py), |pix| pix.clone());
+                    self.push_img_light(res);
+                    return Ok(());
+                }
+                // IMG PROC "point" project (the proc takes and returns
+                // a single POINT)
+                let pointmode = match self.pop_opt_str().as_deref() {
+                    None => false,
+                    Some("point") => true,
+                    Some(other) => {
+                        let msg = format!("project mode not known: {:?}", other);
+                        return Err(ExecError::new(&msg));
+                    },
+                };
                 let proc = self.pop_proc("project")?;
-                let img: Rc<Img<f32>> = self.pop_img("project")?;
+                let img: Rc<Img<f32>> = self.pop_img_light("project")?;
                 
                 let mut subctx = self.clone_env();
                 let mut subexecstack: LendStackIter<ScriptToken> = LendStackIter::new();
                 
                 let res = img.project_mut(|px, py| {
+                    if pointmode {
+                        subctx.execute_proc(&proc, &mut subexecstack, StackValue::Point(px, py))?;
+                        return subctx.pop_point("project proc");
+                    }
                     subctx.execute_proc_2(&proc, &mut subexecstack, StackValue::Float(px), StackValue::Float(py))?;
                     let yval = subctx.pop_as_float("project proc")?;
                     let xval = subctx.pop_as_float("project proc")?;
                     Ok((xval, yval))
                 })?;
-                self.push_img(res);
+                self.push_img_light(res);
             },
 
             BuiltInSymbol::ProjectMap => {
@@ -651,7 +1567,8 @@ impl ExecContext {
                 //### or IMG IMG PROC projectmap?
                 let pixproc = self.pop_proc("projectmap")?;
                 let locproc = self.pop_proc("projectmap")?;
-                let img: Rc<Img<f32>> = self.pop_img("projectmap")?;
+                let img: Rc<Img<f32>> = self.pop_img_light("projectmap")?;
+                let linearlight = self.linearlight;
                 
                 let mut subctx = self.clone_env();
                 let mut subexecstack: LendStackIter<ScriptToken> = LendStackIter::new();
@@ -659,7 +1576,10 @@ impl ExecContext {
                     subctx.execute_proc_2(&locproc, &mut subexecstack, StackValue::Float(px * img.width as f32), StackValue::Float(py * img.height as f32))?;
                     let yval = subctx.pop_as_float("projectmap locproc")?;
                     let xval = subctx.pop_as_float("projectmap locproc")?;
-                    let pix = img.at_lerp(xval, yval);
+                    let mut pix = img.at_lerp(xval, yval);
+                    if linearlight {
+                        pix = pix.to_srgb();
+                    }
                     subctx.execute_proc(&pixproc, &mut subexecstack, StackValue::Color(pix.clone()))?;
                     let pval = subctx.pop_as_color("projectmap pixproc")?;
                     Ok(pval)
@@ -672,7 +1592,7 @@ impl ExecContext {
                 let ypos = self.pop_as_float("at")?;
                 let xpos = self.pop_as_float("at")?;
                 let img: Rc<Img<f32>> = self.pop_img("at")?;
-                let res = img.at_lerp(xpos, ypos);
+                let res = self.sample_light(&img, xpos, ypos);
                 self.push_color(res);
             },
             
@@ -681,7 +1601,7 @@ impl ExecContext {
                 let ypos = self.pop_as_float("nat")?;
                 let xpos = self.pop_as_float("nat")?;
                 let img: Rc<Img<f32>> = self.pop_img("nat")?;
-                let res = img.at_lerp(xpos * img.width as f32, ypos * img.height as f32);
+                let res = self.sample_light(&img, xpos * img.width as f32, ypos * img.height as f32);
                 self.push_color(res);
             },
             
@@ -689,10 +1609,10 @@ impl ExecContext {
                 // IMG1 IMG2 IMGMASK interpolate
                 //### or IMG1 IMG2 PROC interpolate?
                 let imgmask: Rc<Img<f32>> = self.pop_img("interpolate")?;
-                let img2: Rc<Img<f32>> = self.pop_img("interpolate")?;
-                let img1: Rc<Img<f32>> = self.pop_img("interpolate")?;
+                let img2: Rc<Img<f32>> = self.pop_img_light("interpolate")?;
+                let img1: Rc<Img<f32>> = self.pop_img_light("interpolate")?;
                 let res = img1.interp_mask(&img2, &imgmask);
-                self.push_img(res);
+                self.push_img_light(res);
             }
 
             BuiltInSymbol::Contrast => {
@@ -703,41 +1623,614 @@ impl ExecContext {
                 self.push_img(res);
             },
 
-            BuiltInSymbol::Shift => {
-                // IMG SIZE shift, IMG NUM NUM shift
-                let (width, height) = self.pop_as_size("shift")?;
-                let img: Rc<Img<f32>> = self.pop_img("shift")?;
-                let res = img.shift(width, height);
+            BuiltInSymbol::Normalize => {
+                // IMG normalize, POINT normalize (to length 1)
+                if let [.., StackValue::Point(..)] = &self.stack[..] {
+                    let (xval, yval) = self.pop_point("normalize")?;
+                    let len = xval.hypot(yval);
+                    if len == 0.0 {
+                        return Err(ExecError::new("normalize needs nonzero point"));
+                    }
+                    self.push_point(xval / len, yval / len);
+                }
+                else {
+                    let img: Rc<Img<f32>> = self.pop_img("normalize")?;
+                    let res = img.normalize();
+                    self.push_img(res);
+                }
+            },
+
+            BuiltInSymbol::AutoLevels => {
+                // IMG NUM autolevels (NUM is the percent clipped at each end)
+                let pct = self.pop_as_float("autolevels")?;
+                let img: Rc<Img<f32>> = self.pop_img("autolevels")?;
+                if !(0.0..50.0).contains(&pct) {
+                    let msg = format!("autolevels percent must be 0 to 50: {}", pct);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = img.autolevels(pct);
                 self.push_img(res);
             },
 
-            BuiltInSymbol::HalfShift => {
-                let img: Rc<Img<f32>> = self.pop_img("halfshift")?;
-                let res = img.halfshift();
+            BuiltInSymbol::Equalize => {
+                // IMG equalize
+                let img: Rc<Img<f32>> = self.pop_img("equalize")?;
+                let res = img.equalize();
                 self.push_img(res);
             },
 
-            BuiltInSymbol::TileBy => {
-                // IMG SIZE tileby, IMG NUM NUM tileby
-                let (width, height) = self.pop_as_size("tileby")?;
-                let img: Rc<Img<f32>> = self.pop_img("tileby")?;
-                if width <= 0 || height <= 0 {
-                    let msg = format!("tileby size must be positive: {width}x{height}");
+            BuiltInSymbol::Clahe => {
+                // IMG INT NUM clahe (INT tiles across, NUM clip limit)
+                let clip = self.pop_as_float("clahe")?;
+                let tiles = self.pop_int("clahe")?;
+                let img: Rc<Img<f32>> = self.pop_img("clahe")?;
+                if tiles < 1 {
+                    let msg = format!("clahe tiles must be positive: {}", tiles);
                     return Err(ExecError::new(&msg));
                 }
-                let (uwidth, uheight) = (width as usize, height as usize);
-                if img.width * uwidth >= 0x10000 || img.height * uheight > 0x10000 {
-                    let msg = format!("tileby size is too large: {width}x{height}");
+                if clip < 1.0 || clip.is_nan() {
+                    let msg = format!("clahe clip limit must be at least 1: {}", clip);
                     return Err(ExecError::new(&msg));
                 }
-                let res = img.tile_by(uwidth, uheight);
+                let res = img.clahe(tiles as usize, clip);
                 self.push_img(res);
             },
 
-            BuiltInSymbol::Concat => {
-                // IMG... SIZE tilecat, IMG... NUM NUM tilecat
-                let (width, height) = self.pop_as_size("concat")?;
-                if width <= 0 || height <= 0 {
+            BuiltInSymbol::Gamma => {
+                // VAL NUM gamma, VAL COLOR gamma (VAL on the 0..255 scale)
+                let varg2 = self.pop("gamma")?;
+                let varg1 = self.pop("gamma")?;
+                match &varg2 {
+                    StackValue::Integer(val) => self.check_gamma("gamma", &[*val as f32])?,
+                    StackValue::Float(val) => self.check_gamma("gamma", &[*val])?,
+                    StackValue::Color(pix) => self.check_gamma("gamma", &[pix.r, pix.g, pix.b])?,
+                    _ => {},
+                }
+                let res = elementwise_2(varg1, varg2, |val, gam| tone::gamma_value(*val, *gam))?;
+                self.push(res);
+            },
+
+            BuiltInSymbol::Levels => {
+                // IMG INBLACK INWHITE GAMMA OUTBLACK OUTWHITE levels
+                // (each a number or a color)
+                let outwhite = self.pop_as_channels("levels")?;
+                let outblack = self.pop_as_channels("levels")?;
+                let gamma = self.pop_as_channels("levels")?;
+                let inwhite = self.pop_as_channels("levels")?;
+                let inblack = self.pop_as_channels("levels")?;
+                let img: Rc<Img<f32>> = self.pop_img("levels")?;
+                self.check_gamma("levels", &[gamma.r, gamma.g, gamma.b])?;
+                let res = img.levels(&Levels { inblack, inwhite, gamma, outblack, outwhite });
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Curves => {
+                // IMG [ X0 Y0 X1 Y1 ... ] curves
+                // IMG [ [ R points ] [ G points ] [ B points ] ] curves
+                let [rpts, gpts, bpts] = self.pop_curve_points("curves")?;
+                let img: Rc<Img<f32>> = self.pop_img("curves")?;
+                let curves = [ToneCurve::new(&rpts)?, ToneCurve::new(&gpts)?, ToneCurve::new(&bpts)?];
+                let res = img.curves(&curves);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Lut => {
+                // IMG [ NUM NUM ... ] lut, IMG [ COLOR COLOR ... ] lut
+                // (entries spread evenly over 0..255)
+                let tables = self.pop_lut("lut")?;
+                let img: Rc<Img<f32>> = self.pop_img("lut")?;
+                let res = img.lut(&tables);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::ToHSV => {
+                // COLOR tohsv, IMG tohsv (hue 0-360, sat 0-1, value 0-255)
+                self.execute_convert("tohsv", Pix::to_hsv)?;
+            },
+
+            BuiltInSymbol::FromHSV => {
+                // COLOR fromhsv, IMG fromhsv
+                self.execute_convert("fromhsv", Pix::hsv_to_rgb)?;
+            },
+
+            BuiltInSymbol::ToHSL => {
+                // COLOR tohsl, IMG tohsl (hue 0-360, sat 0-1, lightness 0-255)
+                self.execute_convert("tohsl", Pix::to_hsl)?;
+            },
+
+            BuiltInSymbol::FromHSL => {
+                // COLOR fromhsl, IMG fromhsl
+                self.execute_convert("fromhsl", Pix::hsl_to_rgb)?;
+            },
+
+            BuiltInSymbol::ToLab => {
+                // COLOR tolab, IMG tolab (L 0-100, a and b about -128 to 127)
+                self.execute_convert("tolab", Pix::to_lab)?;
+            },
+
+            BuiltInSymbol::FromLab => {
+                // COLOR fromlab, IMG fromlab
+                self.execute_convert("fromlab", Pix::lab_to_rgb)?;
+            },
+
+            BuiltInSymbol::ToYCC => {
+                // COLOR toycc, IMG toycc (Y 0-255, Cb and Cr centred on 128)
+                self.execute_convert("toycc", Pix::to_ycc)?;
+            },
+
+            BuiltInSymbol::FromYCC => {
+                // COLOR fromycc, IMG fromycc
+                self.execute_convert("fromycc", Pix::ycc_to_rgb)?;
+            },
+
+            BuiltInSymbol::ToLinear => {
+                // COLOR tolinear, IMG tolinear
+                self.execute_convert("tolinear", Pix::to_linear)?;
+            },
+
+            BuiltInSymbol::ToSRGB => {
+                // COLOR tosrgb, IMG tosrgb
+                self.execute_convert("tosrgb", Pix::to_srgb)?;
+            },
+
+            BuiltInSymbol::LinearLight => {
+                // BOOL linearlight: resample and blur in linear light
+                let val = self.pop_int("linearlight")?;
+                self.set_linearlight(val != 0);
+            },
+
+            BuiltInSymbol::SplitChannels => {
+                // IMG splitchannels: pushes three grey images (R, G, B)
+                let img: Rc<Img<f32>> = self.pop_img("splitchannels")?;
+                for chan in img.split_channels() {
+                    self.push_img(chan);
+                }
+            },
+
+            BuiltInSymbol::MergeChannels => {
+                // IMG IMG IMG mergechannels: red of the first, green of
+                // the second, blue of the third
+                let bimg: Rc<Img<f32>> = self.pop_img("mergechannels")?;
+                let gimg: Rc<Img<f32>> = self.pop_img("mergechannels")?;
+                let rimg: Rc<Img<f32>> = self.pop_img("mergechannels")?;
+                if rimg.size() != gimg.size() || rimg.size() != bimg.size() {
+                    let msg = format!("mergechannels sizes do not match: {}x{}, {}x{}, {}x{}", rimg.width, rimg.height, gimg.width, gimg.height, bimg.width, bimg.height);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = Img::merge_channels(&rimg, &gimg, &bimg);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Swizzle => {
+                // IMG STR swizzle, COLOR STR swizzle
+                // STR is three of r, g, b, 0, 1 (e.g. "bgr", "rrr", "rg0")
+                let pattern = self.pop_str("swizzle")?;
+                let sources: Vec<ChannelSource> = pattern.chars()
+                    .map(ChannelSource::from_char)
+                    .collect::<Option<Vec<_>>>()
+                    .unwrap_or_default();
+                let sources: [ChannelSource; 3] = sources.try_into()
+                    .map_err(|_| {
+                        let msg = format!("swizzle pattern must be three of r, g, b, 0, 1: {:?}", pattern);
+                        ExecError::new(&msg)
+                    })?;
+                if let [.., StackValue::Image(_)] = &self.stack[..] {
+                    let img: Rc<Img<f32>> = self.pop_img("swizzle")?;
+                    self.push_img(img.swizzle(&sources));
+                }
+                else {
+                    let pix = self.pop_as_color("swizzle")?;
+                    self.push_color(channels::swizzle_pix(&pix, &sources));
+                }
+            },
+
+            BuiltInSymbol::Sobel => {
+                // IMG sobel: pushes magnitude and direction masks
+                let img: Rc<Img<f32>> = self.pop_img("sobel")?;
+                let (magnitude, direction) = img.edge_gradient(GradientKernel::Sobel);
+                self.push_img(magnitude);
+                self.push_img(direction);
+            },
+
+            BuiltInSymbol::Scharr => {
+                // IMG scharr: pushes magnitude and direction masks
+                let img: Rc<Img<f32>> = self.pop_img("scharr")?;
+                let (magnitude, direction) = img.edge_gradient(GradientKernel::Scharr);
+                self.push_img(magnitude);
+                self.push_img(direction);
+            },
+
+            BuiltInSymbol::Laplacian => {
+                // IMG laplacian
+                let img: Rc<Img<f32>> = self.pop_img("laplacian")?;
+                let res = img.laplacian();
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Canny => {
+                // IMG LOW HIGH canny (thresholds on the sobel 0-1 scale)
+                let high = self.pop_as_float("canny")?;
+                let low = self.pop_as_float("canny")?;
+                let img: Rc<Img<f32>> = self.pop_img("canny")?;
+                if !(0.0 <= low && low <= high) {
+                    let msg = format!("canny needs 0 <= low <= high: {} {}", low, high);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = img.canny(low, high);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Erode => {
+                // IMG RAD erode, IMG RAD SHAPE erode (square, disc, cross),
+                // IMG ELEMIMG erode
+                let (img, elem) = self.pop_morph_args("erode")?;
+                let res = img.erode(&elem);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Dilate => {
+                // IMG RAD [SHAPE] dilate, IMG ELEMIMG dilate
+                let (img, elem) = self.pop_morph_args("dilate")?;
+                let res = img.dilate(&elem);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Open => {
+                // IMG RAD [SHAPE] open, IMG ELEMIMG open
+                let (img, elem) = self.pop_morph_args("open")?;
+                let res = img.morph_open(&elem);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Close => {
+                // IMG RAD [SHAPE] close, IMG ELEMIMG close
+                let (img, elem) = self.pop_morph_args("close")?;
+                let res = img.morph_close(&elem);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::TopHat => {
+                // IMG RAD [SHAPE] tophat, IMG ELEMIMG tophat
+                let (img, elem) = self.pop_morph_args("tophat")?;
+                let res = img.tophat(&elem);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::MorphGradient => {
+                // IMG RAD [SHAPE] gradient, IMG ELEMIMG gradient
+                let (img, elem) = self.pop_morph_args("gradient")?;
+                let res = img.morph_gradient(&elem);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::MedianFilter => {
+                // IMG RAD medianfilter (RAD is rounded to a whole pixel)
+                let rad = self.pop_as_float("medianfilter")?;
+                let img: Rc<Img<f32>> = self.pop_img("medianfilter")?;
+                let rad = self.fit_radius("medianfilter radius", rad, &img)?.round() as i32;
+                let res = img.median_filter(rad);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Bilateral => {
+                // IMG RAD RANGE bilateral (RANGE is a 0-255 colour distance)
+                let range = self.pop_as_float("bilateral")?;
+                let rad = self.pop_int("bilateral")?;
+                let img: Rc<Img<f32>> = self.pop_img_light("bilateral")?;
+                if range <= 0.0 || range.is_nan() {
+                    let msg = format!("bilateral range must be positive: {}", range);
+                    return Err(ExecError::new(&msg));
+                }
+                let rad = self.fit_radius("bilateral radius", rad as f32, &img)? as i32;
+                let res = img.bilateral(rad, range);
+                self.push_img_light(res);
+            },
+
+            BuiltInSymbol::NLMeans => {
+                // IMG RAD STRENGTH nlmeans
+                let strength = self.pop_as_float("nlmeans")?;
+                let rad = self.pop_int("nlmeans")?;
+                let img: Rc<Img<f32>> = self.pop_img_light("nlmeans")?;
+                if strength <= 0.0 || strength.is_nan() {
+                    let msg = format!("nlmeans strength must be positive: {}", strength);
+                    return Err(ExecError::new(&msg));
+                }
+                let rad = self.fit_radius("nlmeans radius", rad as f32, &img)? as i32;
+                let res = img.nlmeans(rad, strength);
+                self.push_img_light(res);
+            },
+
+            BuiltInSymbol::Threshold => {
+                // IMG NUM threshold: 1 where the luma is above NUM
+                let level = self.pop_as_float("threshold")?;
+                let img: Rc<Img<f32>> = self.pop_img("threshold")?;
+                let res = img.threshold(level);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Otsu => {
+                // IMG otsu: threshold at the level chosen by Otsu's method
+                let img: Rc<Img<f32>> = self.pop_img("otsu")?;
+                let res = img.threshold(img.otsu_level());
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::AdaptiveThreshold => {
+                // IMG BLOCK OFFSET adaptivethreshold, with optional
+                // "mean" (the default) or "gaussian"
+                let gaussian = match self.pop_opt_str().as_deref() {
+                    None | Some("mean") => false,
+                    Some("gaussian") => true,
+                    Some(other) => {
+                        let msg = format!("adaptivethreshold mode not known: {:?}", other);
+                        return Err(ExecError::new(&msg));
+                    },
+                };
+                let offset = self.pop_as_float("adaptivethreshold")?;
+                let block = self.pop_int("adaptivethreshold")?;
+                let img: Rc<Img<f32>> = self.pop_img("adaptivethreshold")?;
+                if block < 1 {
+                    let msg = format!("adaptivethreshold block must be positive: {}", block);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = img.adaptive_threshold(block as usize, offset, gaussian);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Dither => {
+                // IMG LEVELS dither, IMG PALETTE dither, with optional
+                // "floyd" (the default), "atkinson" or "bayer"
+                let method = match self.pop_opt_str() {
+                    None => DitherMethod::Floyd,
+                    Some(name) => DitherMethod::from_name(&name)
+                        .ok_or_else(|| {
+                            let msg = format!("dither method not known: {:?}", name);
+                            ExecError::new(&msg)
+                        })?,
+                };
+                let quant = self.pop_quantizer("dither")?;
+                let img: Rc<Img<f32>> = self.pop_img("dither")?;
+                let res = img.dither(&quant, method);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Quantize => {
+                // IMG LEVELS quantize, IMG PALETTE quantize
+                let quant = self.pop_quantizer("quantize")?;
+                let img: Rc<Img<f32>> = self.pop_img("quantize")?;
+                let res = img.quantize(&quant);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::MedianCut => {
+                // IMG INT mediancut: a palette array of up to INT colors
+                let count = self.pop_int("mediancut")?;
+                let img: Rc<Img<f32>> = self.pop_img("mediancut")?;
+                if count < 1 {
+                    let msg = format!("mediancut needs at least one color: {}", count);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = img.median_cut(count as usize);
+                self.push_array(res.into_iter().map(StackValue::Color).collect());
+            },
+
+            BuiltInSymbol::KMeans => {
+                // IMG INT kmeans: a palette array of up to INT colors
+                let count = self.pop_int("kmeans")?;
+                let img: Rc<Img<f32>> = self.pop_img("kmeans")?;
+                if count < 1 {
+                    let msg = format!("kmeans needs at least one color: {}", count);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = img.kmeans(count as usize);
+                self.push_array(res.into_iter().map(StackValue::Color).collect());
+            },
+
+            BuiltInSymbol::WrapBytes => {
+                // IMG wrapbytes: channel values taken mod 256
+                let img: Rc<Img<f32>> = self.pop_img("wrapbytes")?;
+                let res = img.wrap_bytes();
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Fft => {
+                // IMG fft: pushes magnitude and phase images
+                // IMG "complex" fft: pushes real and imaginary images
+                let polar = self.pop_fft_mode("fft")?;
+                let img: Rc<Img<f32>> = self.pop_img("fft")?;
+                let (first, second) = img.fft(polar);
+                self.push_img(first);
+                self.push_img(second);
+            },
+
+            BuiltInSymbol::Ifft => {
+                // MAG PHASE ifft, REAL IMAG "complex" ifft
+                let polar = self.pop_fft_mode("ifft")?;
+                let second: Rc<Img<f32>> = self.pop_img("ifft")?;
+                let first: Rc<Img<f32>> = self.pop_img("ifft")?;
+                if first.size() != second.size() {
+                    let msg = format!("ifft sizes do not match: {}x{} vs {}x{}", first.width, first.height, second.width, second.height);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = Img::ifft(&first, &second, polar);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::FftFilter => {
+                // IMG MASK fftfilter (MASK is centred, like fft output)
+                let mask: Rc<Img<f32>> = self.pop_img("fftfilter")?;
+                let img: Rc<Img<f32>> = self.pop_img("fftfilter")?;
+                if img.size() != mask.size() {
+                    let msg = format!("fftfilter sizes do not match: {}x{} vs {}x{}", img.width, img.height, mask.width, mask.height);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = img.fft_filter(&mask);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Homography => {
+                // [SRC POINTS] [DST POINTS] homography: a 9-number matrix
+                // carrying the four SRC points onto the four DST points
+                let dst = self.pop_points("homography")?;
+                let src = self.pop_points("homography")?;
+                let res = warp::homography(&src, &dst)?;
+                self.push_array(res.iter().map(|val| StackValue::Float(*val)).collect());
+            },
+
+            BuiltInSymbol::Warp => {
+                // IMG MATRIX SIZE warp
+                // IMG MATRIX SIZE ["nearest"|"bilinear"|"bicubic"] warp
+                let sampler = match self.pop_opt_str() {
+                    None => Sampler::Bilinear,
+                    Some(name) => Sampler::from_name(&name).ok_or_else(|| {
+                        let msg = format!("warp sampler not known: {:?}", name);
+                        ExecError::new(&msg)
+                    })?,
+                };
+                let (width, height) = self.pop_as_positive_size("warp")?;
+                let mat = self.pop_matrix("warp")?;
+                let img: Rc<Img<f32>> = self.pop_img_light("warp")?;
+                let res = img.warp(&mat, width, height, sampler)?;
+                self.push_img_light(res);
+            },
+
+            BuiltInSymbol::Translate => {
+                // DX DY translate
+                let dy = self.pop_as_float("translate")?;
+                let dx = self.pop_as_float("translate")?;
+                self.push_matrix([1.0, 0.0, dx, 0.0, 1.0, dy]);
+            },
+
+            BuiltInSymbol::Scale => {
+                // SX SY scale
+                let sy = self.pop_as_float("scale")?;
+                let sx = self.pop_as_float("scale")?;
+                self.push_matrix([sx, 0.0, 0.0, 0.0, sy, 0.0]);
+            },
+
+            BuiltInSymbol::Rotate => {
+                // THETA rotate (radians; clockwise on screen, since y
+                // points down)
+                let theta = self.pop_as_float("rotate")?;
+                let (sinth, costh) = theta.sin_cos();
+                self.push_matrix([costh, -sinth, 0.0, sinth, costh, 0.0]);
+            },
+
+            BuiltInSymbol::Skew => {
+                // KX KY skew: x += KX*y, y += KY*x
+                let ky = self.pop_as_float("skew")?;
+                let kx = self.pop_as_float("skew")?;
+                self.push_matrix([1.0, kx, 0.0, ky, 1.0, 0.0]);
+            },
+
+            BuiltInSymbol::Invert => {
+                // MATRIX invert
+                let mat = self.pop_affine("invert")?;
+                let res = warp::affine_invert(&mat).ok_or_else(|| {
+                    let msg = format!("invert matrix is singular: {:?}", mat);
+                    ExecError::new(&msg)
+                })?;
+                self.push_matrix(res);
+            },
+
+            BuiltInSymbol::ConcatMatrix => {
+                // M1 M2 concatmatrix: apply M1, then M2
+                let second = self.pop_affine("concatmatrix")?;
+                let first = self.pop_affine("concatmatrix")?;
+                self.push_matrix(warp::affine_concat(&first, &second));
+            },
+
+            BuiltInSymbol::Transform => {
+                // X Y MATRIX transform, POINT MATRIX transform (the
+                // matrix may also be a 9-number homography)
+                let mat = self.pop_matrix("transform")?;
+                let ispoint = matches!(&self.stack[..], [.., StackValue::Point(..)]);
+                let (xval, yval) = if ispoint {
+                    self.pop_point("transform")?
+                }
+                else {
+                    let yval = self.pop_as_float("transform")?;
+                    let xval = self.pop_as_float("transform")?;
+                    (xval, yval)
+                };
+                let wval = mat[6] * xval + mat[7] * yval + mat[8];
+                let newx = (mat[0] * xval + mat[1] * yval + mat[2]) / wval;
+                let newy = (mat[3] * xval + mat[4] * yval + mat[5]) / wval;
+                if ispoint {
+                    self.push_point(newx, newy);
+                }
+                else {
+                    self.push_float(newx);
+                    self.push_float(newy);
+                }
+            },
+
+            BuiltInSymbol::Point => {
+                // NUM NUM point, SIZE point, POINT point
+                match &self.stack[..] {
+                    [.., StackValue::Point(..)] => {},
+                    [.., StackValue::Size(width, height)] => {
+                        let (xval, yval) = (*width as f32, *height as f32);
+                        self.stack.pop();
+                        self.push_point(xval, yval);
+                    },
+                    _ => {
+                        let yval = self.pop_as_float("point")?;
+                        let xval = self.pop_as_float("point")?;
+                        self.push_point(xval, yval);
+                    },
+                }
+            },
+
+            BuiltInSymbol::Dot => {
+                // POINT POINT dot
+                let (x2, y2) = self.pop_point("dot")?;
+                let (x1, y1) = self.pop_point("dot")?;
+                self.push_float(x1 * x2 + y1 * y2);
+            },
+
+            BuiltInSymbol::Shift => {
+                // IMG SIZE shift, IMG NUM NUM shift
+                let (width, height) = self.pop_as_size("shift")?;
+                let img: Rc<Img<f32>> = self.pop_img("shift")?;
+                let res = img.shift(width, height);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::HalfShift => {
+                let img: Rc<Img<f32>> = self.pop_img("halfshift")?;
+                let res = img.halfshift();
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::TileBy => {
+                // IMG SIZE tileby, IMG NUM NUM tileby
+                let (width, height) = self.pop_as_size("tileby")?;
+                let img: Rc<Img<f32>> = self.pop_img("tileby")?;
+                if width <= 0 || height <= 0 {
+                    let msg = format!("tileby size must be positive: {width}x{height}");
+                    return Err(ExecError::new(&msg));
+                }
+                let (uwidth, uheight) = (width as usize, height as usize);
+                if img.width * uwidth >= 0x10000 || img.height * uheight > 0x10000 {
+                    let msg = format!("tileby size is too large: {width}x{height}");
+                    return Err(ExecError::new(&msg));
+                }
+                let res = img.tile_by(uwidth, uheight);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Concat => {
+                // STR STR concat
+                // IMG... SIZE tilecat, IMG... NUM NUM tilecat
+                if let [.., StackValue::String(_), StackValue::String(_)] = &self.stack[..] {
+                    let val2 = self.pop_str("concat")?;
+                    let val1 = self.pop_str("concat")?;
+                    self.push_str(val1 + &val2);
+                    return Ok(());
+                }
+                let (width, height) = self.pop_as_size("concat")?;
+                if width <= 0 || height <= 0 {
                     let msg = format!("tilecat size must be positive: {width}x{height}");
                     return Err(ExecError::new(&msg));
                 }
@@ -778,6 +2271,195 @@ impl ExecContext {
                 self.push_img(res);
             },
 
+            BuiltInSymbol::LinearGradient => {
+                // SIZE X0 Y0 X1 Y1 STOPS lineargradient
+                let linear = self.pop_linear_mode("lineargradient")?;
+                let stops = self.pop_color_stops("lineargradient")?;
+                let y1 = self.pop_as_float("lineargradient")?;
+                let x1 = self.pop_as_float("lineargradient")?;
+                let y0 = self.pop_as_float("lineargradient")?;
+                let x0 = self.pop_as_float("lineargradient")?;
+                let (width, height) = self.pop_as_positive_size("lineargradient")?;
+                let grad = Gradient::new(&stops, linear);
+                let res = Img::linear_gradient(width, height, (x0, y0), (x1, y1), &grad);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::RadialGradient => {
+                // SIZE CX CY RADIUS STOPS radialgradient
+                let linear = self.pop_linear_mode("radialgradient")?;
+                let stops = self.pop_color_stops("radialgradient")?;
+                let rad = self.pop_as_float("radialgradient")?;
+                let cy = self.pop_as_float("radialgradient")?;
+                let cx = self.pop_as_float("radialgradient")?;
+                let (width, height) = self.pop_as_positive_size("radialgradient")?;
+                if rad <= 0.0 {
+                    let msg = format!("radialgradient radius must be positive: {rad}");
+                    return Err(ExecError::new(&msg));
+                }
+                let grad = Gradient::new(&stops, linear);
+                let res = Img::radial_gradient(width, height, (cx, cy), rad, &grad);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::ConicGradient => {
+                // SIZE CX CY ANGLE STOPS conicgradient
+                let linear = self.pop_linear_mode("conicgradient")?;
+                let stops = self.pop_color_stops("conicgradient")?;
+                let angle = self.pop_as_float("conicgradient")?;
+                let cy = self.pop_as_float("conicgradient")?;
+                let cx = self.pop_as_float("conicgradient")?;
+                let (width, height) = self.pop_as_positive_size("conicgradient")?;
+                let grad = Gradient::new(&stops, linear);
+                let res = Img::conic_gradient(width, height, (cx, cy), angle, &grad);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::CornerGradient => {
+                // SIZE TOPLEFT TOPRIGHT BOTTOMLEFT BOTTOMRIGHT cornergradient
+                let linear = self.pop_linear_mode("cornergradient")?;
+                let bottomright = self.pop_as_color("cornergradient")?;
+                let bottomleft = self.pop_as_color("cornergradient")?;
+                let topright = self.pop_as_color("cornergradient")?;
+                let topleft = self.pop_as_color("cornergradient")?;
+                let (width, height) = self.pop_as_positive_size("cornergradient")?;
+                let res = Img::corner_gradient(width, height, &[topleft, topright, bottomleft, bottomright], linear);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Line => {
+                // IMG X0 Y0 X1 Y1 COLOR WIDTH line
+                let (rule, blend) = self.pop_draw_options("line")?;
+                let width = self.pop_as_float("line")?;
+                let color = self.pop_as_color("line")?;
+                let y1 = self.pop_as_float("line")?;
+                let x1 = self.pop_as_float("line")?;
+                let y0 = self.pop_as_float("line")?;
+                let x0 = self.pop_as_float("line")?;
+                let img: Rc<Img<f32>> = self.pop_img("line")?;
+                if width <= 0.0 {
+                    let msg = format!("line width must be positive: {width}");
+                    return Err(ExecError::new(&msg));
+                }
+                let res = img.draw_path(&[(x0, y0), (x1, y1)], false, width, rule, &Paint { color, blend });
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Polyline => {
+                // IMG POINTS COLOR WIDTH polyline
+                let (rule, blend) = self.pop_draw_options("polyline")?;
+                let width = self.pop_as_float("polyline")?;
+                let color = self.pop_as_color("polyline")?;
+                let pts = self.pop_points("polyline")?;
+                let img: Rc<Img<f32>> = self.pop_img("polyline")?;
+                if width <= 0.0 {
+                    let msg = format!("polyline width must be positive: {width}");
+                    return Err(ExecError::new(&msg));
+                }
+                if pts.is_empty() {
+                    return Err(ExecError::new("polyline needs points"));
+                }
+                let res = img.draw_path(&pts, false, width, rule, &Paint { color, blend });
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Rect => {
+                // IMG X Y W H COLOR WIDTH rect (WIDTH 0 to fill)
+                let (rule, blend) = self.pop_draw_options("rect")?;
+                let width = self.pop_as_float("rect")?;
+                let color = self.pop_as_color("rect")?;
+                let rheight = self.pop_as_float("rect")?;
+                let rwidth = self.pop_as_float("rect")?;
+                let ry = self.pop_as_float("rect")?;
+                let rx = self.pop_as_float("rect")?;
+                let img: Rc<Img<f32>> = self.pop_img("rect")?;
+                let pts = [(rx, ry), (rx+rwidth, ry), (rx+rwidth, ry+rheight), (rx, ry+rheight)];
+                let res = img.draw_path(&pts, true, width, rule, &Paint { color, blend });
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Circle => {
+                // IMG CX CY RAD COLOR WIDTH circle (WIDTH 0 to fill)
+                let (rule, blend) = self.pop_draw_options("circle")?;
+                let width = self.pop_as_float("circle")?;
+                let color = self.pop_as_color("circle")?;
+                let rad = self.pop_as_float("circle")?;
+                let cy = self.pop_as_float("circle")?;
+                let cx = self.pop_as_float("circle")?;
+                let img: Rc<Img<f32>> = self.pop_img("circle")?;
+                let pts = draw::ellipse_points((cx, cy), rad, rad);
+                let res = img.draw_path(&pts, true, width, rule, &Paint { color, blend });
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Ellipse => {
+                // IMG CX CY RX RY COLOR WIDTH ellipse (WIDTH 0 to fill)
+                let (rule, blend) = self.pop_draw_options("ellipse")?;
+                let width = self.pop_as_float("ellipse")?;
+                let color = self.pop_as_color("ellipse")?;
+                let ry = self.pop_as_float("ellipse")?;
+                let rx = self.pop_as_float("ellipse")?;
+                let cy = self.pop_as_float("ellipse")?;
+                let cx = self.pop_as_float("ellipse")?;
+                let img: Rc<Img<f32>> = self.pop_img("ellipse")?;
+                let pts = draw::ellipse_points((cx, cy), rx, ry);
+                let res = img.draw_path(&pts, true, width, rule, &Paint { color, blend });
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Polygon => {
+                // IMG POINTS COLOR WIDTH polygon (WIDTH 0 to fill)
+                let (rule, blend) = self.pop_draw_options("polygon")?;
+                let width = self.pop_as_float("polygon")?;
+                let color = self.pop_as_color("polygon")?;
+                let pts = self.pop_points("polygon")?;
+                let img: Rc<Img<f32>> = self.pop_img("polygon")?;
+                if pts.is_empty() {
+                    return Err(ExecError::new("polygon needs points"));
+                }
+                let res = img.draw_path(&pts, true, width, rule, &Paint { color, blend });
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Bezier => {
+                // IMG POINTS COLOR WIDTH bezier (WIDTH 0 to fill)
+                // POINTS is a start point and then control, control, end
+                // for each cubic segment.
+                let (rule, blend) = self.pop_draw_options("bezier")?;
+                let width = self.pop_as_float("bezier")?;
+                let color = self.pop_as_color("bezier")?;
+                let pts = self.pop_points("bezier")?;
+                let img: Rc<Img<f32>> = self.pop_img("bezier")?;
+                if pts.len() < 4 || !(pts.len() - 1).is_multiple_of(3) {
+                    let msg = format!("bezier needs 1+3n points: {}", pts.len());
+                    return Err(ExecError::new(&msg));
+                }
+                let path = draw::bezier_points(&pts);
+                let res = img.draw_path(&path, width <= 0.0, width, rule, &Paint { color, blend });
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Text => {
+                // IMG STR X Y COLOR SCALE text
+                // IMG STR X Y COLOR SCALE FONTFILE text (.bdf or .psf)
+                let font = match self.pop_opt_str() {
+                    Some(filename) => Font::load(&filename)?,
+                    None => Font::builtin(),
+                };
+                let scale = self.pop_as_float("text")?;
+                let color = self.pop_as_color("text")?;
+                let ypos = self.pop_as_float("text")?;
+                let xpos = self.pop_as_float("text")?;
+                let text = self.pop_str("text")?;
+                let img: Rc<Img<f32>> = self.pop_img("text")?;
+                if scale <= 0.0 || scale.is_nan() {
+                    let msg = format!("text scale must be positive: {}", scale);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = img.draw_text(&text, xpos, ypos, scale, &font, &Paint { color, blend: BlendMode::Normal });
+                self.push_img(res);
+            },
+
             BuiltInSymbol::Holify => {
                 // IMG NUM holify
                 let val = self.pop_as_float("holify")?;
@@ -789,11 +2471,52 @@ impl ExecContext {
             BuiltInSymbol::TaxiBlur => {
                 // IMG INT taxiblur
                 let val = self.pop_int("taxiblur")?;
-                let img: Rc<Img<f32>> = self.pop_img("taxiblur")?;
+                let img: Rc<Img<f32>> = self.pop_img_light("taxiblur")?;
                 let res = img.taxiblur(val);
-                self.push_img(res);
+                self.push_img_light(res);
             },
             
+            BuiltInSymbol::GaussBlur => {
+                // IMG SIGMA gaussblur
+                let sigma = self.pop_as_float("gaussblur")?;
+                let img: Rc<Img<f32>> = self.pop_img_light("gaussblur")?;
+                let sigma = self.fit_radius("gaussblur sigma", sigma, &img)?;
+                let res = img.gaussblur(sigma);
+                self.push_img_light(res);
+            },
+
+            BuiltInSymbol::Unsharp => {
+                // IMG SIGMA AMOUNT THRESHOLD unsharp
+                let threshold = self.pop_as_float("unsharp")?;
+                let amount = self.pop_as_float("unsharp")?;
+                let sigma = self.pop_as_float("unsharp")?;
+                let img: Rc<Img<f32>> = self.pop_img_light("unsharp")?;
+                let sigma = self.fit_radius("unsharp sigma", sigma, &img)?;
+                let res = img.unsharp(sigma, amount, threshold);
+                self.push_img_light(res);
+            },
+
+            BuiltInSymbol::HighPass => {
+                // IMG SIGMA highpass
+                let sigma = self.pop_as_float("highpass")?;
+                let img: Rc<Img<f32>> = self.pop_img("highpass")?;
+                let sigma = self.fit_radius("highpass sigma", sigma, &img)?;
+                let res = img.highpass(sigma);
+                self.push_img(res);
+            },
+
+            BuiltInSymbol::Flatten => {
+                // IMG INT flatten
+                let rad = self.pop_int("flatten")?;
+                let img: Rc<Img<f32>> = self.pop_img_light("flatten")?;
+                let rad = self.fit_radius("flatten radius", rad as f32, &img)? as i32;
+                if rad == 0 {
+                    return Err(ExecError::new("flatten radius must be positive"));
+                }
+                let res = img.flatten(rad);
+                self.push_img_light(res);
+            },
+
             BuiltInSymbol::Seamless => {
                 // IMG NUM seamless
                 let val = self.pop_as_float("seamless")?;
@@ -807,4 +2530,434 @@ impl ExecContext {
         
         Ok(())
     }
+
+    fn execute_convert(&mut self, label: &str, func: fn(&Pix<f32>) -> Pix<f32>) -> Result<(), ExecError> {
+        // Shared body of the colour-space builtins: IMG or COLOR.
+        if let [.., StackValue::Image(_)] = &self.stack[..] {
+            let img: Rc<Img<f32>> = self.pop_img(label)?;
+            self.push_img(img.map(func));
+        }
+        else {
+            let pix = self.pop_as_color(label)?;
+            self.push_color(func(&pix));
+        }
+        Ok(())
+    }
+
+    fn check_gamma(&self, label: &str, gammas: &[f32]) -> Result<(), ExecError> {
+        // A gamma of zero or less has no sensible curve.
+        for gam in gammas {
+            if *gam <= 0.0 || gam.is_nan() {
+                let msg = format!("{} gamma must be positive: {}", label, gam);
+                return Err(ExecError::new(&msg));
+            }
+        }
+        Ok(())
+    }
+
+    fn fit_radius(&self, label: &str, rad: f32, img: &Img<f32>) -> Result<f32, ExecError> {
+        // The one rule for filter radii (and blur sigmas): negative is
+        // an error, and anything past the image size is cut down to
+        // it, since the filter window is clipped at the edges anyway.
+        if rad < 0.0 || rad.is_nan() {
+            let msg = format!("{} must not be negative: {}", label, rad);
+            return Err(ExecError::new(&msg));
+        }
+        Ok(rad.min((img.width + img.height) as f32))
+    }
+
+    fn pop_morph_args(&mut self, label: &str) -> Result<(Rc<Img<f32>>, StructElem), ExecError> {
+        // IMG and a structuring element: RAD, RAD SHAPE, or an image.
+        let shape = self.pop_opt_str();
+        if shape.is_none() {
+            if let [.., StackValue::Image(_), StackValue::Image(_)] = &self.stack[..] {
+                let elemimg: Rc<Img<f32>> = self.pop_img(label)?;
+                let img: Rc<Img<f32>> = self.pop_img(label)?;
+                return Ok((img, StructElem::from_img(&elemimg)));
+            }
+        }
+        let rad = self.pop_int(label)?;
+        let img: Rc<Img<f32>> = self.pop_img(label)?;
+        let rad = self.fit_radius(&format!("{} radius", label), rad as f32, &img)? as i32;
+        let shape = shape.unwrap_or_else(|| "square".to_string());
+        let elem = StructElem::from_name(&shape, rad)
+            .ok_or_else(|| {
+                let msg = format!("{} shape not known: {:?}", label, shape);
+                ExecError::new(&msg)
+            })?;
+        Ok((img, elem))
+    }
+
+    fn pop_quantizer(&mut self, label: &str) -> Result<Quantizer, ExecError> {
+        // LEVELS (an integer, at least 2) or a nonempty array of colors.
+        if let [.., StackValue::Array(_)] = &self.stack[..] {
+            let arr = self.pop_array(label)?;
+            let colors: Vec<Pix<f32>> = arr.iter()
+                .map(|val| match val {
+                    StackValue::Color(pix) => Ok(pix.clone()),
+                    _ => {
+                        let msg = format!("{} palette needs colors: {:?}", label, val);
+                        Err(ExecError::new(&msg))
+                    },
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if colors.is_empty() {
+                let msg = format!("{} palette is empty", label);
+                return Err(ExecError::new(&msg));
+            }
+            return Ok(Quantizer::Palette(colors));
+        }
+        let levels = self.pop_int(label)?;
+        if levels < 2 {
+            let msg = format!("{} needs at least 2 levels: {}", label, levels);
+            return Err(ExecError::new(&msg));
+        }
+        Ok(Quantizer::Levels(levels as usize))
+    }
+
+    fn pop_fft_mode(&mut self, label: &str) -> Result<bool, ExecError> {
+        // Optional "polar" (the default) or "complex"; true means polar.
+        match self.pop_opt_str().as_deref() {
+            None | Some("polar") => Ok(true),
+            Some("complex") => Ok(false),
+            Some(other) => {
+                let msg = format!("{} mode not known: {:?}", label, other);
+                Err(ExecError::new(&msg))
+            },
+        }
+    }
+
+    fn sample_light(&self, img: &Img<f32>, xpos: f32, ypos: f32) -> Pix<f32> {
+        // Bilinear sample, blending in linear light if that mode is on.
+        if !self.linearlight {
+            return img.at_lerp(xpos, ypos);
+        }
+        let x0 = xpos.floor();
+        let y0 = ypos.floor();
+        let (xfrac, yfrac) = (xpos - x0, ypos - y0);
+        let corner = |dx: i32, dy: i32| img.at_clamped(x0 as i32 + dx, y0 as i32 + dy).to_linear();
+        let (p00, p01, p10, p11) = (corner(0, 0), corner(1, 0), corner(0, 1), corner(1, 1));
+        let mix = |v00: f32, v01: f32, v10: f32, v11: f32| {
+            let top = v00 + (v01 - v00) * xfrac;
+            let bottom = v10 + (v11 - v10) * xfrac;
+            top + (bottom - top) * yfrac
+        };
+        Pix::new(mix(p00.r, p01.r, p10.r, p11.r), mix(p00.g, p01.g, p10.g, p11.g), mix(p00.b, p01.b, p10.b, p11.b)).to_srgb()
+    }
+
+    fn pop_draw_options(&mut self, label: &str) -> Result<(FillRule, BlendMode), ExecError> {
+        // Optional mode words: a fill rule and/or a blend mode.
+        let mut rule = FillRule::NonZero;
+        let mut blend = BlendMode::Normal;
+        if let Some(mode) = self.pop_opt_str() {
+            for word in mode.split_whitespace() {
+                match word {
+                    "evenodd" => { rule = FillRule::EvenOdd; },
+                    "nonzero" => { rule = FillRule::NonZero; },
+                    _ => {
+                        blend = BlendMode::from_name(word)
+                            .ok_or_else(|| {
+                                let msg = format!("{} mode not known: {:?}", label, word);
+                                ExecError::new(&msg)
+                            })?;
+                    },
+                }
+            }
+        }
+        Ok((rule, blend))
+    }
+
+    fn pop_noise_tile(&mut self, label: &str) -> Result<bool, ExecError> {
+        // Pop the optional "tile" mode of a noise builtin.
+        let mode = self.pop_opt_str();
+        match mode.as_deref() {
+            None => Ok(false),
+            Some("tile") => Ok(true),
+            Some(other) => {
+                let msg = format!("{} mode not known: {:?}", label, other);
+                Err(ExecError::new(&msg))
+            },
+        }
+    }
+
+    fn execute_noise(&mut self, label: &str, kind: NoiseKind, fractal: Option<Fractal>, tile: bool) -> Result<(), ExecError> {
+        // Shared tail of the noise builtins: X Y (sample one point) or
+        // SIZE CELLS (generate a grey image).
+        let seed = self.noiseseed.get();
+        let sample = |xpos: f32, ypos: f32, period: Option<(i32, i32)>| {
+            match fractal {
+                None => noise::noise(kind, seed, xpos, ypos, period),
+                Some(frac) => noise::fractal(kind, seed, xpos, ypos, period, &frac),
+            }
+        };
+        let signed = match (kind, fractal) {
+            (NoiseKind::Worley, _) => false,
+            (_, Some(frac)) => !frac.turbulence,
+            (_, None) => true,
+        };
+
+        let val = self.pop_as_float(label)?;
+        match self.stack.last() {
+            Some(StackValue::Size(..)) | Some(StackValue::Image(..)) => {
+                let (width, height) = self.pop_as_size(label)?;
+                if width <= 0 || height <= 0 {
+                    let msg = format!("{} size must be positive: {width}x{height}", label);
+                    return Err(ExecError::new(&msg));
+                }
+                if val <= 0.0 {
+                    let msg = format!("{} cells must be positive: {val}", label);
+                    return Err(ExecError::new(&msg));
+                }
+                let res = Img::new_noise(width as usize, height as usize, val, tile, |xpos, ypos, period| {
+                    let nval = sample(xpos, ypos, period);
+                    let unit = if signed { nval * 0.5 + 0.5 } else { nval };
+                    255.0 * unit.clamp(0.0, 1.0)
+                });
+                self.push_img(res);
+            },
+            _ => {
+                if tile {
+                    let msg = format!("{} tile mode needs SIZE CELLS", label);
+                    return Err(ExecError::new(&msg));
+                }
+                let xpos = self.pop_as_float(label)?;
+                self.push_float(sample(xpos, val, None));
+            },
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::parse::load_script_text;
+
+    // An 8x6 image with four different corners, for tests that just need
+    // some image on the stack.
+    const FIXTURE: &str = "8x6 $F00 $0F0 $00F $FFF cornergradient";
+
+    fn run(body: &str) -> ExecContext {
+        let script = load_script_text(body).unwrap();
+        let mut ctx = ExecContext::new(Some(1));
+        ctx.execute_script(&script).unwrap();
+        ctx
+    }
+
+    fn top_img(ctx: &ExecContext) -> Rc<Img<f32>> {
+        match ctx.stack().last() {
+            Some(StackValue::Image(img)) => Rc::clone(img),
+            other => panic!("expected an image, found {:?}", other),
+        }
+    }
+
+    fn rgb(pix: &Pix<f32>) -> (f32, f32, f32) {
+        (pix.r, pix.g, pix.b)
+    }
+
+    #[test]
+    fn integer_arithmetic() {
+        assert!(matches!(run("3 2 +").stack(), [StackValue::Integer(5)]));
+        assert!(matches!(run("7 2 %").stack(), [StackValue::Integer(1)]));
+        assert!(matches!(run("7 2 idiv").stack(), [StackValue::Integer(3)]));
+        assert!(matches!(run("2147483647 1 +").stack(), [StackValue::Float(val)] if *val == 2147483648.0));
+        assert!(matches!(run("65536 65536 *").stack(), [StackValue::Float(val)] if *val == 4294967296.0));
+    }
+
+    fn top_float(ctx: &ExecContext) -> f32 {
+        match ctx.stack().last() {
+            Some(StackValue::Float(val)) => *val,
+            Some(StackValue::Integer(val)) => *val as f32,
+            other => panic!("expected a number, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn math_builtins() {
+        let cases = [
+            ("16 sqrt", 4.0), ("2 10 pow", 1024.0), ("1000 log10", 3.0),
+            ("0 3 - abs", 3.0), ("2.5 floor", 2.0), ("2.5 ceil", 3.0),
+            ("0 2.5 - round", -3.0), ("3.75 fract", 0.75), ("0 4 - sign", -1.0),
+            ("7 0 5 clamp", 5.0), ("0 10 0.25 lerp", 2.5), ("1 2 step", 1.0),
+            ("2 1 step", 0.0), ("0 1 0.5 smoothstep", 0.5), ("3 5 min", 3.0),
+            ("3 5 max", 5.0),
+        ];
+        for (body, want) in cases {
+            assert_eq!(top_float(&run(body)), want, "{}", body);
+        }
+        assert!((top_float(&run("e ln")) - 1.0).abs() < 1.0e-6);
+        assert!((top_float(&run("0 exp")) - 1.0).abs() < 1.0e-6);
+        assert!((top_float(&run("1 0 atan2")) - std::f32::consts::FRAC_PI_2).abs() < 1.0e-6);
+        assert_eq!(top_float(&run("tau")), std::f32::consts::TAU);
+
+        // Colours and images go channel by channel.
+        let ctx = run("$F80 $48F max");
+        assert!(matches!(ctx.stack(), [StackValue::Color(pix)] if rgb(pix) == (255.0, 136.0, 255.0)));
+        let img = top_img(&run(&format!("{} 16 min", FIXTURE)));
+        assert_eq!(rgb(img.at(0, 0)), (16.0, 0.0, 0.0));
+        assert_eq!(rgb(img.at(7, 5)), (16.0, 16.0, 16.0));
+    }
+
+    #[test]
+    fn equality_and_logic() {
+        let cases = [
+            ("3 3 ==", 1), ("3 4 ==", 0), ("3 4 !=", 1), ("3 3.0 !=", 0),
+            ("1 1.05 0.1 approx", 1), ("1 1.5 0.1 approx", 0),
+            ("0 not", 1), ("2 not", 0), ("1 0 xor", 1), ("1 1 xor", 0),
+        ];
+        for (body, want) in cases {
+            assert!(matches!(run(body).stack(), [StackValue::Integer(val)] if *val == want), "{}", body);
+        }
+
+        // Colours and images give masks, so they combine with and/or.
+        let ctx = run("$F00 $F80 ==");
+        assert!(matches!(ctx.stack(), [StackValue::Color(pix)] if rgb(pix) == (1.0, 0.0, 1.0)));
+        let ctx = run("$F00 $0F0 or");
+        assert!(matches!(ctx.stack(), [StackValue::Color(pix)] if rgb(pix) == (1.0, 1.0, 0.0)));
+        let img = top_img(&run(&format!("{} dup 0 == swap 255 == or", FIXTURE)));
+        assert_eq!(rgb(img.at(0, 0)), (1.0, 1.0, 1.0));
+        assert_eq!(rgb(img.at(7, 5)), (1.0, 1.0, 1.0));
+        let img = top_img(&run(&format!("{} dup 0 == swap 255 == and", FIXTURE)));
+        assert!(img.pixels.iter().all(|pix| rgb(pix) == (0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn gamma_must_be_positive() {
+        assert!(matches!(run("255 2 gamma").stack(), [StackValue::Float(val)] if *val == 255.0));
+        for body in ["128 0 gamma", "128 -1 gamma", "0 -1 gamma", "128 $F0F gamma"] {
+            assert!(fails(body), "{} should fail", body);
+        }
+        assert!(fails(&format!("{} 0 255 0 0 255 levels", FIXTURE)));
+    }
+
+    #[test]
+    fn format_fills_placeholders() {
+        assert!(matches!(run("\"out_{}.ppm\" 3 format").stack(), [StackValue::String(val)] if val == "out_3.ppm"));
+        assert!(matches!(run("\"{}x{}\" [4 2.5] format").stack(), [StackValue::String(val)] if val == "4x2.5"));
+        assert!(fails("\"{}x{}\" 4 format"));
+    }
+
+    #[test]
+    fn channel_builtins() {
+        let img = top_img(&run(&format!("{} splitchannels mergechannels", FIXTURE)));
+        assert_eq!(rgb(img.at(7, 0)), (0.0, 255.0, 0.0));
+        let img = top_img(&run(&format!("{} \"bgr\" swizzle", FIXTURE)));
+        assert_eq!(rgb(img.at(0, 0)), (0.0, 0.0, 255.0));
+        assert!(fails(&format!("{} \"bgx\" swizzle", FIXTURE)));
+        assert!(fails(&format!("{} splitchannels pop 2x2 0 image mergechannels", FIXTURE)));
+    }
+
+    #[test]
+    fn corner_gradient_corners() {
+        let ctx = run("32x32 $F00 $0F0 $00F $FFF cornergradient");
+        let img = top_img(&ctx);
+        assert_eq!(rgb(img.at(0, 0)), (255.0, 0.0, 0.0));
+        assert_eq!(rgb(img.at(31, 0)), (0.0, 255.0, 0.0));
+        assert_eq!(rgb(img.at(0, 31)), (0.0, 0.0, 255.0));
+        assert_eq!(rgb(img.at(31, 31)), (255.0, 255.0, 255.0));
+    }
+
+    #[test]
+    fn filter_huge_radius() {
+        // Oversized radii are cut down to the image size; negative ones
+        // are errors.
+        for tail in ["2147483647 medianfilter", "3.4 medianfilter", "2147483647 2 bilateral", "2147483647 2 nlmeans"] {
+            let ctx = run(&format!("{} {}", FIXTURE, tail));
+            assert!(matches!(ctx.stack(), [StackValue::Image(_)]), "{}", tail);
+        }
+        for tail in ["-1 medianfilter", "-1 2 bilateral", "-1 2 nlmeans", "2 0 bilateral"] {
+            let body = format!("{} {}", FIXTURE, tail);
+            assert!(fails(&body), "{} should fail", body);
+        }
+    }
+
+    #[test]
+    fn blur_huge_sigma() {
+        for tail in ["1e30 gaussblur", "1e30 highpass", "1e30 1 0 unsharp", "2147483647 flatten"] {
+            let ctx = run(&format!("{} {}", FIXTURE, tail));
+            assert!(matches!(ctx.stack(), [StackValue::Image(_)]), "{}", tail);
+        }
+        for tail in ["-1 gaussblur", "-1 highpass", "-1 1 0 unsharp", "0 flatten", "-3 flatten"] {
+            let body = format!("{} {}", FIXTURE, tail);
+            assert!(fails(&body), "{} should fail", body);
+        }
+    }
+
+    #[test]
+    fn highpass_flat_is_mid_grey() {
+        // Even with linear light on, the offset lands on plain mid-grey.
+        let ctx = run("1 linearlight 4x4 $468 image 2 highpass");
+        let img = top_img(&ctx);
+        for pix in img.pixels.iter() {
+            for val in [pix.r, pix.g, pix.b] {
+                assert!((val - 127.5).abs() < 0.01, "{}", val);
+            }
+        }
+    }
+
+    fn top_point(ctx: &ExecContext) -> (f32, f32) {
+        match ctx.stack().last() {
+            Some(StackValue::Point(xval, yval)) => (*xval, *yval),
+            other => panic!("expected a point, found {:?}", other),
+        }
+    }
+
+    fn fails(body: &str) -> bool {
+        let script = load_script_text(body).unwrap();
+        let mut ctx = ExecContext::new(Some(1));
+        ctx.execute_script(&script).is_err()
+    }
+
+    #[test]
+    fn matrix_chaining() {
+        // Each step makes a fresh matrix, so two sit side by side until
+        // concatmatrix joins them.
+        let ctx = run("1 2 translate 3 4 translate");
+        assert!(matches!(ctx.stack(), [StackValue::Matrix(_), StackValue::Matrix(_)]));
+        let (xval, yval) = top_point(&run("0,0 1 2 translate 3 4 translate concatmatrix transform"));
+        assert_eq!((xval, yval), (4.0, 6.0));
+        let (xval, yval) = top_point(&run("1,0 2 3 scale 5 0 translate concatmatrix transform"));
+        assert_eq!((xval, yval), (7.0, 0.0));
+        let body = "80,60 -80 -60 translate 0.3 rotate concatmatrix 80 60 translate concatmatrix transform";
+        let (xval, yval) = top_point(&run(body));
+        assert!((xval - 80.0).abs() < 1.0e-3 && (yval - 60.0).abs() < 1.0e-3);
+        let (xval, yval) = top_point(&run("5,7 0.7 rotate 2 3 skew concatmatrix dup invert concatmatrix transform"));
+        assert!((xval - 5.0).abs() < 1.0e-3 && (yval - 7.0).abs() < 1.0e-3);
+        assert!(fails("1 2 translate concatmatrix"));
+        assert!(fails("0 0 scale invert"));
+    }
+
+    #[test]
+    fn point_arithmetic() {
+        assert_eq!(top_point(&run("1,2 3,4.5 +")), (4.0, 6.5));
+        assert_eq!(top_point(&run("1,2 3,4 -")), (-2.0, -2.0));
+        assert_eq!(top_point(&run("1,2 3 *")), (3.0, 6.0));
+        assert_eq!(top_point(&run("2 1,4 /")), (2.0, 0.5));
+        assert_eq!(top_point(&run("4x6 0.5 *")), (2.0, 3.0));
+        assert_eq!(top_point(&run("1 2 point 1,1 +")), (2.0, 3.0));
+        assert!(matches!(run("1,2 3,4 dot").stack(), [StackValue::Float(val)] if *val == 11.0));
+    }
+
+    #[test]
+    fn point_length_normalize() {
+        assert!(matches!(run("3,4 length").stack(), [StackValue::Float(val)] if *val == 5.0));
+        let (xval, yval) = top_point(&run("3,-4 normalize"));
+        assert!((xval - 0.6).abs() < 1.0e-6 && (yval + 0.8).abs() < 1.0e-6);
+        assert!(fails("0,0 normalize"));
+        assert!(matches!(run("\"abc\" length").stack(), [StackValue::Integer(3)]));
+        assert!(matches!(run("4x4 $468 image normalize").stack(), [StackValue::Image(_)]));
+    }
+
+    #[test]
+    fn morph_huge_radius() {
+        // The window covers the whole image, so every pixel gets the
+        // image's minimum (or maximum).
+        let ctx = run(&format!("{} 2147483647 \"disc\" erode", FIXTURE));
+        let img = top_img(&ctx);
+        assert!(img.pixels.iter().all(|pix| rgb(pix) == (0.0, 0.0, 0.0)));
+        let ctx = run(&format!("{} 2147483647 dilate", FIXTURE));
+        let img = top_img(&ctx);
+        assert!(img.pixels.iter().all(|pix| rgb(pix) == (255.0, 255.0, 255.0)));
+    }
 }
@@ -28,6 +28,7 @@ impl<T> LendStackIter<T> {
         self.stack.pop();
     }
 
+    #[allow(clippy::needless_return)]
     pub fn next(&mut self) -> Option<&T> {
         loop {
             let last = self.stack.last()?;  // or return None
@@ -27,12 +27,14 @@ impl Error for ExecError {
 }
 
 impl From<String> for ExecError {
+    #[allow(clippy::needless_return)]
     fn from(err: String) -> ExecError {
         return ExecError::new(&err);
     }
 }
 
 impl From<ppmio::PPMError> for ExecError {
+    #[allow(clippy::needless_return)]
     fn from(err: ppmio::PPMError) -> ExecError {
         return ExecError::new(&err.to_string());
     }
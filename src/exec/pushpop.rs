@@ -1,7 +1,10 @@
 use std::rc::Rc;
+use std::collections::HashMap;
 
 use crate::img::pixel::Pix;
 use crate::img::Img;
+use crate::img::gradient::ColorStop;
+use crate::img::warp;
 use crate::script::ScriptToken;
 use crate::exec::except::ExecError;
 use crate::exec::ExecContext;
@@ -94,6 +97,18 @@ impl ExecContext {
         }
     }
 
+    pub fn pop_dict(&mut self, label: &str) -> Result<Rc<HashMap<String, StackValue>>, ExecError> {
+        let val = self.pop(label)?;
+        
+        if let StackValue::Dict(dict) = val {
+            Ok(dict)
+        }
+        else {
+            let msg = format!("{} needs dict: {:?}", label, val);
+            Err(ExecError::new(&msg))
+        }
+    }
+
     pub fn pop_as_float(&mut self, label: &str) -> Result<f32, ExecError> {
         let val = self.pop(label)?;
 
@@ -107,6 +122,17 @@ impl ExecContext {
         }
     }
 
+    pub fn pop_point(&mut self, label: &str) -> Result<(f32, f32), ExecError> {
+        let val = self.pop(label)?;
+        if let StackValue::Point(xval, yval) = val {
+            Ok((xval, yval))
+        }
+        else {
+            let msg = format!("{} needs point: {:?}", label, val);
+            Err(ExecError::new(&msg))
+        }
+    }
+
     pub fn pop_as_size(&mut self, label: &str) -> Result<(i32, i32), ExecError> {
         match &self.stack[..] {
             [.., StackValue::Image(img) ] => {
@@ -133,21 +159,22 @@ impl ExecContext {
     }
     
     pub fn pop_as_color(&mut self, label: &str) -> Result<Pix<f32>, ExecError> {
+        fn numval(val: &StackValue) -> Option<f32> {
+            match val {
+                StackValue::Integer(ival) => Some(*ival as f32),
+                StackValue::Float(fval) => Some(*fval),
+                _ => None,
+            }
+        }
+        
         match &self.stack[..] {
             [.., StackValue::Color(pix) ] => {
                 let res = pix.clone();
                 self.pop(label)?;
                 Ok(res)
             },
-            [.., StackValue::Integer(rval), StackValue::Integer(gval), StackValue::Integer(bval) ] => {
-                let res: Pix<f32> = Pix::new(*rval as f32, *gval as f32, *bval as f32);
-                self.pop(label)?;
-                self.pop(label)?;
-                self.pop(label)?;
-                Ok(res)
-            },
-            [.., StackValue::Float(rval), StackValue::Float(gval), StackValue::Float(bval) ] => {
-                let res: Pix<f32> = Pix::new(*rval, *gval, *bval);
+            [.., rval, gval, bval ] if numval(rval).is_some() && numval(gval).is_some() && numval(bval).is_some() => {
+                let res: Pix<f32> = Pix::new(numval(rval).unwrap(), numval(gval).unwrap(), numval(bval).unwrap());
                 self.pop(label)?;
                 self.pop(label)?;
                 self.pop(label)?;
@@ -160,6 +187,181 @@ impl ExecContext {
         }
     }
     
+    pub fn pop_color_stops(&mut self, label: &str) -> Result<Vec<ColorStop>, ExecError> {
+        // [ [POS COLOR] [POS COLOR] ... ]
+        let arr = self.pop_array(label)?;
+        let mut res: Vec<ColorStop> = Vec::with_capacity(arr.len());
+        for entry in arr.iter() {
+            let stop = match entry {
+                StackValue::Array(pair) => match &pair[..] {
+                    [StackValue::Float(pos), StackValue::Color(pix)] => Some(ColorStop { pos: *pos, color: pix.clone() }),
+                    [StackValue::Integer(pos), StackValue::Color(pix)] => Some(ColorStop { pos: *pos as f32, color: pix.clone() }),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let stop = stop.ok_or_else(|| {
+                let msg = format!("{} stop needs [pos color]: {:?}", label, entry);
+                ExecError::new(&msg)
+            })?;
+            res.push(stop);
+        }
+        if res.is_empty() {
+            let msg = format!("{} needs at least one stop", label);
+            return Err(ExecError::new(&msg));
+        }
+        Ok(res)
+    }
+    
+    pub fn pop_points(&mut self, label: &str) -> Result<Vec<(f32, f32)>, ExecError> {
+        // [ X0 Y0 X1 Y1 ... ] or [ P0 P1 ... ]
+        let arr = self.pop_array(label)?;
+        array_points(&arr, label)
+    }
+    
+    pub fn pop_affine(&mut self, label: &str) -> Result<[f32; 6], ExecError> {
+        let val = self.pop(label)?;
+        if let StackValue::Matrix(mat) = val {
+            Ok(mat)
+        }
+        else {
+            let msg = format!("{} needs matrix: {:?}", label, val);
+            Err(ExecError::new(&msg))
+        }
+    }
+    
+    pub fn pop_matrix(&mut self, label: &str) -> Result<[f32; 9], ExecError> {
+        // MATRIX, or [ A B C D E F ] (affine) or [ A B C D E F G H I ]
+        // (projective), row-major.
+        if let [.., StackValue::Matrix(_)] = &self.stack[..] {
+            let mat = self.pop_affine(label)?;
+            return Ok(warp::affine_to_full(&mat));
+        }
+        let arr = self.pop_array(label)?;
+        let nums: Vec<f32> = arr.iter()
+            .map(|val| match val {
+                StackValue::Integer(ival) => Some(*ival as f32),
+                StackValue::Float(fval) => Some(*fval),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| {
+                let msg = format!("{} matrix must be numbers: {:?}", label, arr);
+                ExecError::new(&msg)
+            })?;
+        match nums[..] {
+            [m0, m1, m2, m3, m4, m5] => Ok([m0, m1, m2, m3, m4, m5, 0.0, 0.0, 1.0]),
+            [m0, m1, m2, m3, m4, m5, m6, m7, m8] => Ok([m0, m1, m2, m3, m4, m5, m6, m7, m8]),
+            _ => {
+                let msg = format!("{} matrix needs 6 or 9 numbers, not {}", label, nums.len());
+                Err(ExecError::new(&msg))
+            },
+        }
+    }
+    
+    pub fn pop_curve_points(&mut self, label: &str) -> Result<[Vec<(f32, f32)>; 3], ExecError> {
+        // [ X0 Y0 X1 Y1 ... ] for all channels, or
+        // [ [ R points ] [ G points ] [ B points ] ]
+        let arr = self.pop_array(label)?;
+        if let [StackValue::Array(rarr), StackValue::Array(garr), StackValue::Array(barr)] = &arr[..] {
+            return Ok([array_points(rarr, label)?, array_points(garr, label)?, array_points(barr, label)?]);
+        }
+        let pts = array_points(&arr, label)?;
+        Ok([pts.clone(), pts.clone(), pts])
+    }
+    
+    pub fn pop_lut(&mut self, label: &str) -> Result<[Vec<f32>; 3], ExecError> {
+        // [ NUM NUM ... ] for all channels, or [ COLOR COLOR ... ]
+        let arr = self.pop_array(label)?;
+        if arr.is_empty() {
+            let msg = format!("{} needs a nonempty table", label);
+            return Err(ExecError::new(&msg));
+        }
+        let mut res: [Vec<f32>; 3] = [Vec::new(), Vec::new(), Vec::new()];
+        for val in arr.iter() {
+            let pix = match val {
+                StackValue::Integer(ival) => Pix::grey(*ival as f32),
+                StackValue::Float(fval) => Pix::grey(*fval),
+                StackValue::Color(pix) => pix.clone(),
+                _ => {
+                    let msg = format!("{} needs array of numbers or colors: {:?}", label, val);
+                    return Err(ExecError::new(&msg));
+                },
+            };
+            res[0].push(pix.r);
+            res[1].push(pix.g);
+            res[2].push(pix.b);
+        }
+        Ok(res)
+    }
+    
+    pub fn pop_as_channels(&mut self, label: &str) -> Result<Pix<f32>, ExecError> {
+        // A color, or one number for all three channels.
+        let val = self.pop(label)?;
+        match val {
+            StackValue::Color(pix) => Ok(pix),
+            StackValue::Integer(ival) => Ok(Pix::grey(ival as f32)),
+            StackValue::Float(fval) => Ok(Pix::grey(fval)),
+            _ => {
+                let msg = format!("{} needs color or number: {:?}", label, val);
+                Err(ExecError::new(&msg))
+            }
+        }
+    }
+    
+    pub fn pop_as_positive_size(&mut self, label: &str) -> Result<(usize, usize), ExecError> {
+        let (width, height) = self.pop_as_size(label)?;
+        if width <= 0 || height <= 0 {
+            let msg = format!("{} size must be positive: {width}x{height}", label);
+            return Err(ExecError::new(&msg));
+        }
+        Ok((width as usize, height as usize))
+    }
+    
+    pub fn pop_linear_mode(&mut self, label: &str) -> Result<bool, ExecError> {
+        // Optional "srgb" (the default) or "linear" interpolation mode.
+        match self.pop_opt_str().as_deref() {
+            None | Some("srgb") => Ok(false),
+            Some("linear") => Ok(true),
+            Some(other) => {
+                let msg = format!("{} mode not known: {:?}", label, other);
+                Err(ExecError::new(&msg))
+            },
+        }
+    }
+    
+    pub fn pop_opt_str(&mut self) -> Option<String> {
+        // Pop an optional trailing mode string, if there is one.
+        if let [.., StackValue::String(_)] = &self.stack[..] {
+            if let Some(StackValue::String(val)) = self.stack.pop() {
+                return Some(val);
+            }
+        }
+        None
+    }
+    
+    pub fn pop_img_light(&mut self, label: &str) -> Result<Rc<Img<f32>>, ExecError> {
+        // Pop an image to be resampled or blurred; in linear-light mode
+        // it comes back converted to linear. Use push_img_light for the
+        // result.
+        let img = self.pop_img(label)?;
+        if self.linearlight {
+            Ok(Rc::new(img.to_linear()))
+        }
+        else {
+            Ok(img)
+        }
+    }
+
+    pub fn push_img_light(&mut self, val: Img<f32>) {
+        if self.linearlight {
+            self.push_img(val.to_srgb());
+        }
+        else {
+            self.push_img(val);
+        }
+    }
+
     pub fn push(&mut self, val: StackValue) {
         self.stack.push(val);
     }
@@ -180,6 +382,10 @@ impl ExecContext {
         self.stack.push(StackValue::Size(width, height));
     }
 
+    pub fn push_point(&mut self, xval: f32, yval: f32) {
+        self.stack.push(StackValue::Point(xval, yval));
+    }
+
     pub fn push_color(&mut self, val: Pix<f32>) {
         self.stack.push(StackValue::Color(val));
     }
@@ -197,4 +403,40 @@ impl ExecContext {
         self.stack.push(StackValue::Array(Rc::new(val)));
     }
 
+    pub fn push_matrix(&mut self, val: [f32; 6]) {
+        self.stack.push(StackValue::Matrix(val));
+    }
+
+    pub fn push_dict(&mut self, val: HashMap<String, StackValue>) {
+        self.stack.push(StackValue::Dict(Rc::new(val)));
+    }
+
+}
+
+fn array_points(arr: &[StackValue], label: &str) -> Result<Vec<(f32, f32)>, ExecError> {
+    // Coordinates may be loose numbers or points, or a mix.
+    let mut nums: Vec<f32> = Vec::with_capacity(arr.len() * 2);
+    for val in arr {
+        match val {
+            StackValue::Integer(ival) => nums.push(*ival as f32),
+            StackValue::Float(fval) => nums.push(*fval),
+            StackValue::Point(xval, yval) => {
+                if !nums.len().is_multiple_of(2) {
+                    let msg = format!("{} point splits a coordinate pair: {:?}", label, val);
+                    return Err(ExecError::new(&msg));
+                }
+                nums.push(*xval);
+                nums.push(*yval);
+            },
+            _ => {
+                let msg = format!("{} needs array of coordinates: {:?}", label, val);
+                return Err(ExecError::new(&msg));
+            },
+        }
+    }
+    if !nums.len().is_multiple_of(2) {
+        let msg = format!("{} needs an even number of coordinates", label);
+        return Err(ExecError::new(&msg));
+    }
+    Ok(nums.chunks(2).map(|pair| (pair[0], pair[1])).collect())
 }
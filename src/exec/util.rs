@@ -5,6 +5,15 @@ use crate::img::Img;
 use crate::exec::StackValue;
 use crate::exec::except::ExecError;
 
+fn promote(arg: StackValue) -> StackValue {
+    // Integers act as floats, and sizes as points.
+    match arg {
+        StackValue::Integer(ival) => StackValue::Float(ival as f32),
+        StackValue::Size(width, height) => StackValue::Point(width as f32, height as f32),
+        other => other,
+    }
+}
+
 pub fn elementwise<F>(arg: StackValue, func: F) -> Result<StackValue, ExecError>
     where F: Fn(&f32) -> f32 {
     
@@ -15,6 +24,12 @@ pub fn elementwise<F>(arg: StackValue, func: F) -> Result<StackValue, ExecError>
         StackValue::Float(fval) => {
             Ok(StackValue::Float(func(&fval)))
         },
+        StackValue::Size(width, height) => {
+            Ok(StackValue::Point(func(&(width as f32)), func(&(height as f32))))
+        },
+        StackValue::Point(xval, yval) => {
+            Ok(StackValue::Point(func(&xval), func(&yval)))
+        },
         StackValue::Color(pval) => {
             let res: Pix<f32> = Pix::new(func(&pval.r), func(&pval.g), func(&pval.b));
             Ok(StackValue::Color(res))
@@ -40,6 +55,11 @@ pub fn elementwise_bool<F>(arg: StackValue, func: F) -> Result<StackValue, ExecE
         StackValue::Float(fval) => {
             Ok(StackValue::Integer(if func(&fval) {1} else {0}))
         },
+        StackValue::Point(xval, yval) => {
+            Ok(StackValue::Point(
+                if func(&xval) {1.0} else {0.0},
+                if func(&yval) {1.0} else {0.0}))
+        },
         StackValue::Color(pval) => {
             let res: Pix<f32> = Pix::new(
                 if func(&pval.r) {1.0} else {0.0},
@@ -61,23 +81,22 @@ pub fn elementwise_bool<F>(arg: StackValue, func: F) -> Result<StackValue, ExecE
 pub fn elementwise_2<F>(varg1: StackValue, varg2: StackValue, func: F) -> Result<StackValue, ExecError>
     where F: Fn(&f32, &f32) -> f32 {
     
-    let arg2 = if let StackValue::Integer(ival) = varg2 {
-        StackValue::Float(ival as f32)
-    }
-    else {
-        varg2
-    };
-    let arg1 = if let StackValue::Integer(ival) = varg1 {
-        StackValue::Float(ival as f32)
-    }
-    else {
-        varg1
-    };
+    let arg2 = promote(varg2);
+    let arg1 = promote(varg1);
     
     match (arg1, arg2) {
         (StackValue::Float(f1), StackValue::Float(f2)) => {
             Ok(StackValue::Float(func(&f1, &f2)))
         },
+        (StackValue::Point(x1, y1), StackValue::Point(x2, y2)) => {
+            Ok(StackValue::Point(func(&x1, &x2), func(&y1, &y2)))
+        },
+        (StackValue::Point(xval, yval), StackValue::Float(fl)) => {
+            Ok(StackValue::Point(func(&xval, &fl), func(&yval, &fl)))
+        },
+        (StackValue::Float(fl), StackValue::Point(xval, yval)) => {
+            Ok(StackValue::Point(func(&fl, &xval), func(&fl, &yval)))
+        },
         (StackValue::Color(p1), StackValue::Color(p2)) => {
             let res: Pix<f32> = Pix::new(func(&p1.r, &p2.r), func(&p1.g, &p2.g), func(&p1.b, &p2.b));
             Ok(StackValue::Color(res))
@@ -122,26 +141,47 @@ pub fn elementwise_2<F>(varg1: StackValue, varg2: StackValue, func: F) -> Result
 
 }
 
+pub fn elementwise_int_2<F, G>(varg1: StackValue, varg2: StackValue, ifunc: G, func: F) -> Result<StackValue, ExecError>
+    where F: Fn(&f32, &f32) -> f32,
+          G: Fn(i32, i32) -> Option<i32> {
+    // Like elementwise_2, but two integers produce an integer. If the
+    // integer op fails (overflow, or a zero divisor), fall back to
+    // floats.
+
+    if let (StackValue::Integer(i1), StackValue::Integer(i2)) = (&varg1, &varg2) {
+        if let Some(res) = ifunc(*i1, *i2) {
+            return Ok(StackValue::Integer(res));
+        }
+    }
+
+    elementwise_2(varg1, varg2, func)
+}
+
 pub fn elementwise_bool_2<F>(varg1: StackValue, varg2: StackValue, func: F) -> Result<StackValue, ExecError>
     where F: Fn(&f32, &f32) -> bool {
     
-    let arg2 = if let StackValue::Integer(ival) = varg2 {
-        StackValue::Float(ival as f32)
-    }
-    else {
-        varg2
-    };
-    let arg1 = if let StackValue::Integer(ival) = varg1 {
-        StackValue::Float(ival as f32)
-    }
-    else {
-        varg1
-    };
+    let arg2 = promote(varg2);
+    let arg1 = promote(varg1);
     
     match (arg1, arg2) {
         (StackValue::Float(f1), StackValue::Float(f2)) => {
             Ok(StackValue::Integer(if func(&f1, &f2) {1} else {0} ))
         },
+        (StackValue::Point(x1, y1), StackValue::Point(x2, y2)) => {
+            Ok(StackValue::Point(
+                if func(&x1, &x2) {1.0} else {0.0},
+                if func(&y1, &y2) {1.0} else {0.0}))
+        },
+        (StackValue::Point(xval, yval), StackValue::Float(fl)) => {
+            Ok(StackValue::Point(
+                if func(&xval, &fl) {1.0} else {0.0},
+                if func(&yval, &fl) {1.0} else {0.0}))
+        },
+        (StackValue::Float(fl), StackValue::Point(xval, yval)) => {
+            Ok(StackValue::Point(
+                if func(&fl, &xval) {1.0} else {0.0},
+                if func(&fl, &yval) {1.0} else {0.0}))
+        },
         (StackValue::Color(p1), StackValue::Color(p2)) => {
             let res: Pix<f32> = Pix::new(
                 if func(&p1.r, &p2.r) {1.0} else {0.0},
@@ -204,3 +244,24 @@ pub fn elementwise_bool_2<F>(varg1: StackValue, varg2: StackValue, func: F) -> R
 pub fn sigmoid(val: f32, sharp: f32) -> f32 {
     1.0 / (1.0 + (-sharp*(2.0*val-1.0)).exp())
 }
+
+pub fn stringify(val: &StackValue) -> String {
+    match val {
+        StackValue::String(strval) => strval.clone(),
+        StackValue::Integer(ival) => ival.to_string(),
+        StackValue::Float(fval) => fval.to_string(),
+        StackValue::Size(width, height) => format!("{}x{}", width, height),
+        StackValue::Point(xval, yval) => format!("{},{}", xval, yval),
+        StackValue::Color(pix) => {
+            let rval = pix.r.round().clamp(0.0, 255.0) as u8;
+            let gval = pix.g.round().clamp(0.0, 255.0) as u8;
+            let bval = pix.b.round().clamp(0.0, 255.0) as u8;
+            format!("${:02X}{:02X}{:02X}", rval, gval, bval)
+        },
+        StackValue::Array(arr) => {
+            let ls: Vec<String> = arr.iter().map(stringify).collect();
+            format!("[{}]", ls.join(" "))
+        },
+        _ => format!("{:?}", val),
+    }
+}
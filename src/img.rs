@@ -6,6 +6,21 @@ use crate::exec::except::ExecError;
 pub mod pixel;
 pub mod imgmath;
 pub mod ppmio;
+pub mod random;
+pub mod noise;
+pub mod colorspace;
+pub mod gradient;
+pub mod draw;
+pub mod font5x7;
+pub mod text;
+pub mod stats;
+pub mod tone;
+pub mod channels;
+pub mod edge;
+pub mod morph;
+pub mod quantize;
+pub mod fft;
+pub mod warp;
 
 pub struct Img<T> {
     pub filename: Option<String>,
@@ -24,6 +39,7 @@ impl<T> fmt::Debug for Img<T> {
 }
 
 impl<T: Copy> Img<T> {
+    #[allow(clippy::let_and_return)]
     pub fn new_grey(width: usize, height: usize, val: T) -> Img<T> {
         let pix: Pix<T> = Pix { r:val, g:val, b:val };
         let res = Img {
@@ -38,6 +54,7 @@ impl<T: Copy> Img<T> {
 }
 
 impl<T: Default + Clone> Img<T> {
+    #[allow(clippy::let_and_return)]
     pub fn new(width: usize, height: usize) -> Img<T> {
         let pix: Pix<T> = Pix::default();
         let res = Img {
@@ -96,6 +113,7 @@ impl<T: Default + Clone> Img<T> {
 }
 
 impl<T: Clone> Img<T> {
+    #[allow(clippy::let_and_return)]
     pub fn new_constant(width: usize, height: usize, pix: Pix<T>) -> Img<T> {
         let res = Img {
             filename: None,
@@ -0,0 +1,114 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Splitting, merging and rearranging colour channels.
+
+#[derive(Debug, Clone, Copy)]
+pub enum ChannelSource {
+    Red,
+    Green,
+    Blue,
+    Zero,
+    Full,
+}
+
+impl ChannelSource {
+    pub fn from_char(ch: char) -> Option<ChannelSource> {
+        match ch {
+            'r' => Some(ChannelSource::Red),
+            'g' => Some(ChannelSource::Green),
+            'b' => Some(ChannelSource::Blue),
+            '0' => Some(ChannelSource::Zero),
+            '1' => Some(ChannelSource::Full),
+            _ => None,
+        }
+    }
+
+    fn pick(&self, pix: &Pix<f32>) -> f32 {
+        match self {
+            ChannelSource::Red => pix.r,
+            ChannelSource::Green => pix.g,
+            ChannelSource::Blue => pix.b,
+            ChannelSource::Zero => 0.0,
+            ChannelSource::Full => 255.0,
+        }
+    }
+}
+
+pub fn swizzle_pix(pix: &Pix<f32>, pattern: &[ChannelSource; 3]) -> Pix<f32> {
+    Pix::new(pattern[0].pick(pix), pattern[1].pick(pix), pattern[2].pick(pix))
+}
+
+impl Img<f32> {
+
+    pub fn split_channels(&self) -> [Img<f32>; 3] {
+        // Three grey images.
+        [
+            self.map(|pix| Pix::grey(pix.r)),
+            self.map(|pix| Pix::grey(pix.g)),
+            self.map(|pix| Pix::grey(pix.b)),
+        ]
+    }
+
+    pub fn merge_channels(rimg: &Img<f32>, gimg: &Img<f32>, bimg: &Img<f32>) -> Img<f32> {
+        // Take the red channel of the first image, green of the second,
+        // blue of the third. (For grey images it makes no difference.)
+        // The sizes must match.
+        let mut res = Img::new(rimg.width, rimg.height);
+        for (index, pix) in res.pixels.iter_mut().enumerate() {
+            *pix = Pix::new(rimg.pixels[index].r, gimg.pixels[index].g, bimg.pixels[index].b);
+        }
+        res
+    }
+
+    pub fn swizzle(&self, pattern: &[ChannelSource; 3]) -> Img<f32> {
+        self.map(|pix| swizzle_pix(pix, pattern))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_img() -> Img<f32> {
+        let mut img = Img::new(3, 2);
+        for (index, pix) in img.pixels.iter_mut().enumerate() {
+            let val = index as f32 * 40.0;
+            *pix = Pix::new(val, 255.0 - val, val * 0.5 + 10.0);
+        }
+        img
+    }
+
+    fn rgbs(img: &Img<f32>) -> Vec<(f32, f32, f32)> {
+        img.pixels.iter().map(|pix| (pix.r, pix.g, pix.b)).collect()
+    }
+
+    fn pattern(text: &str) -> [ChannelSource; 3] {
+        let sources: Vec<ChannelSource> = text.chars().map(|ch| ChannelSource::from_char(ch).unwrap()).collect();
+        [sources[0], sources[1], sources[2]]
+    }
+
+    #[test]
+    fn split_merge_round_trip() {
+        let img = sample_img();
+        let [rimg, gimg, bimg] = img.split_channels();
+        assert!(gimg.pixels.iter().zip(&img.pixels).all(|(grey, pix)| (grey.r, grey.g, grey.b) == (pix.g, pix.g, pix.g)));
+        assert_eq!(rgbs(&Img::merge_channels(&rimg, &gimg, &bimg)), rgbs(&img));
+        // Merging in a different order swaps the channels.
+        assert_eq!(rgbs(&Img::merge_channels(&bimg, &gimg, &rimg)), rgbs(&img.swizzle(&pattern("bgr"))));
+    }
+
+    #[test]
+    fn swizzle_patterns() {
+        let img = sample_img();
+        assert_eq!(rgbs(&img.swizzle(&pattern("rgb"))), rgbs(&img));
+        assert_eq!(rgbs(&img.swizzle(&pattern("bgr")).swizzle(&pattern("bgr"))), rgbs(&img));
+        let pix = Pix::new(10.0, 20.0, 30.0);
+        let res = swizzle_pix(&pix, &pattern("rr0"));
+        assert_eq!((res.r, res.g, res.b), (10.0, 10.0, 0.0));
+        let res = swizzle_pix(&pix, &pattern("1gb"));
+        assert_eq!((res.r, res.g, res.b), (255.0, 20.0, 30.0));
+        assert!(ChannelSource::from_char('x').is_none());
+    }
+}
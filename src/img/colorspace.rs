@@ -0,0 +1,231 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Pixel values are gamma-encoded sRGB in the range 0-255. These convert
+// a single channel to and from linear light, on the same 0-255 scale.
+
+pub fn srgb_to_linear(val: f32) -> f32 {
+    let unit = val / 255.0;
+    let lin = if unit <= 0.04045 {
+        unit / 12.92
+    }
+    else {
+        ((unit + 0.055) / 1.055).powf(2.4)
+    };
+    lin * 255.0
+}
+
+pub fn linear_to_srgb(val: f32) -> f32 {
+    let unit = val / 255.0;
+    let enc = if unit <= 0.0031308 {
+        unit * 12.92
+    }
+    else {
+        1.055 * unit.powf(1.0 / 2.4) - 0.055
+    };
+    enc * 255.0
+}
+
+impl Pix<f32> {
+    pub fn to_linear(&self) -> Pix<f32> {
+        Pix::new(srgb_to_linear(self.r), srgb_to_linear(self.g), srgb_to_linear(self.b))
+    }
+
+    pub fn to_srgb(&self) -> Pix<f32> {
+        Pix::new(linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b))
+    }
+}
+
+// Alternative colour spaces. These are stored in a Pix as three
+// channels, but are not RGB:
+//   HSV, HSL: hue in degrees (0-360), saturation 0-1, value or
+//     lightness 0-255.
+//   Lab: CIE L*a*b* with a D65 white point; L 0-100, a and b roughly
+//     -128 to 127.
+//   YCbCr: full-range (JPEG) BT.601; Y 0-255, Cb and Cr centred on 128.
+
+fn lab_f(val: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    if val > DELTA * DELTA * DELTA {
+        val.cbrt()
+    }
+    else {
+        val / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+    }
+}
+
+fn lab_finv(val: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    if val > DELTA {
+        val * val * val
+    }
+    else {
+        3.0 * DELTA * DELTA * (val - 4.0 / 29.0)
+    }
+}
+
+const WHITE_X: f32 = 0.95047;
+const WHITE_Z: f32 = 1.08883;
+
+impl Pix<f32> {
+    fn hue_chroma(&self) -> (f32, f32, f32, f32) {
+        // Returns (hue, chroma, max, min).
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let chroma = max - min;
+        let hue = if chroma <= 0.0 {
+            0.0
+        }
+        else if max == self.r {
+            60.0 * ((self.g - self.b) / chroma).rem_euclid(6.0)
+        }
+        else if max == self.g {
+            60.0 * ((self.b - self.r) / chroma + 2.0)
+        }
+        else {
+            60.0 * ((self.r - self.g) / chroma + 4.0)
+        };
+        (hue, chroma, max, min)
+    }
+
+    fn from_hue_chroma(hue: f32, chroma: f32, min: f32) -> Pix<f32> {
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let xval = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+        let (r, g, b) = match sector as i32 {
+            0 => (chroma, xval, 0.0),
+            1 => (xval, chroma, 0.0),
+            2 => (0.0, chroma, xval),
+            3 => (0.0, xval, chroma),
+            4 => (xval, 0.0, chroma),
+            _ => (chroma, 0.0, xval),
+        };
+        Pix::new(r + min, g + min, b + min)
+    }
+
+    pub fn to_hsv(&self) -> Pix<f32> {
+        let (hue, chroma, max, _) = self.hue_chroma();
+        let sat = if max > 0.0 { chroma / max } else { 0.0 };
+        Pix::new(hue, sat, max)
+    }
+
+    pub fn hsv_to_rgb(&self) -> Pix<f32> {
+        let (hue, sat, val) = (self.r, self.g.clamp(0.0, 1.0), self.b);
+        let chroma = val * sat;
+        Pix::from_hue_chroma(hue, chroma, val - chroma)
+    }
+
+    pub fn to_hsl(&self) -> Pix<f32> {
+        let (hue, chroma, max, min) = self.hue_chroma();
+        let light = (max + min) * 0.5;
+        let denom = 255.0 - (2.0 * light - 255.0).abs();
+        let sat = if denom > 0.0 { chroma / denom } else { 0.0 };
+        Pix::new(hue, sat, light)
+    }
+
+    pub fn hsl_to_rgb(&self) -> Pix<f32> {
+        let (hue, sat, light) = (self.r, self.g.clamp(0.0, 1.0), self.b);
+        let chroma = (255.0 - (2.0 * light - 255.0).abs()) * sat;
+        Pix::from_hue_chroma(hue, chroma, light - chroma * 0.5)
+    }
+
+    pub fn to_lab(&self) -> Pix<f32> {
+        let lin = self.to_linear();
+        let (r, g, b) = (lin.r / 255.0, lin.g / 255.0, lin.b / 255.0);
+        let xval = 0.4124564 * r + 0.3575761 * g + 0.1804375 * b;
+        let yval = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+        let zval = 0.0193339 * r + 0.119192 * g + 0.9503041 * b;
+        let (fx, fy, fz) = (lab_f(xval / WHITE_X), lab_f(yval), lab_f(zval / WHITE_Z));
+        Pix::new(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+    }
+
+    pub fn lab_to_rgb(&self) -> Pix<f32> {
+        let fy = (self.r + 16.0) / 116.0;
+        let fx = fy + self.g / 500.0;
+        let fz = fy - self.b / 200.0;
+        let (xval, yval, zval) = (WHITE_X * lab_finv(fx), lab_finv(fy), WHITE_Z * lab_finv(fz));
+        let r = 3.2404542 * xval - 1.5371385 * yval - 0.4985314 * zval;
+        let g = -0.969266 * xval + 1.8760108 * yval + 0.041556 * zval;
+        let b = 0.0556434 * xval - 0.2040259 * yval + 1.0572252 * zval;
+        Pix::new(r * 255.0, g * 255.0, b * 255.0).to_srgb()
+    }
+
+    pub fn to_ycc(&self) -> Pix<f32> {
+        let (r, g, b) = (self.r, self.g, self.b);
+        Pix::new(
+            0.299 * r + 0.587 * g + 0.114 * b,
+            128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b,
+            128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b)
+    }
+
+    pub fn ycc_to_rgb(&self) -> Pix<f32> {
+        let (y, cb, cr) = (self.r, self.g - 128.0, self.b - 128.0);
+        Pix::new(
+            y + 1.402 * cr,
+            y - 0.344136 * cb - 0.714136 * cr,
+            y + 1.772 * cb)
+    }
+}
+
+impl Img<f32> {
+    pub fn to_linear(&self) -> Img<f32> {
+        self.map(|pix| pix.to_linear())
+    }
+
+    pub fn to_srgb(&self) -> Img<f32> {
+        self.map(|pix| pix.to_srgb())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Pix<f32>> {
+        let levels = [0.0, 1.0, 37.0, 128.0, 200.0, 255.0];
+        let mut res = Vec::new();
+        for r in levels {
+            for g in levels {
+                for b in levels {
+                    res.push(Pix::new(r, g, b));
+                }
+            }
+        }
+        res
+    }
+
+    fn assert_close(orig: &Pix<f32>, back: &Pix<f32>, tol: f32, space: &str) {
+        let diff = (orig.r - back.r).abs().max((orig.g - back.g).abs()).max((orig.b - back.b).abs());
+        assert!(diff < tol, "{} round trip: {:?} came back as {:?}", space, orig, back);
+    }
+
+    #[test]
+    fn linear_round_trip() {
+        for pix in samples() {
+            assert_close(&pix, &pix.to_linear().to_srgb(), 0.01, "linear");
+        }
+    }
+
+    #[test]
+    fn hsv_hsl_round_trip() {
+        for pix in samples() {
+            assert_close(&pix, &pix.to_hsv().hsv_to_rgb(), 0.01, "hsv");
+            assert_close(&pix, &pix.to_hsl().hsl_to_rgb(), 0.01, "hsl");
+        }
+    }
+
+    #[test]
+    fn lab_ycc_round_trip() {
+        for pix in samples() {
+            assert_close(&pix, &pix.to_lab().lab_to_rgb(), 0.05, "lab");
+            assert_close(&pix, &pix.to_ycc().ycc_to_rgb(), 0.05, "ycc");
+        }
+    }
+
+    #[test]
+    fn known_values() {
+        assert_close(&Pix::new(0.0, 1.0, 255.0), &Pix::new(255.0, 0.0, 0.0).to_hsv(), 1.0e-4, "hsv red");
+        assert_close(&Pix::new(120.0, 1.0, 127.5), &Pix::new(0.0, 255.0, 0.0).to_hsl(), 1.0e-4, "hsl green");
+        assert_close(&Pix::new(100.0, 0.0, 0.0), &Pix::new(255.0, 255.0, 255.0).to_lab(), 0.01, "lab white");
+        assert_close(&Pix::new(255.0, 128.0, 128.0), &Pix::new(255.0, 255.0, 255.0).to_ycc(), 0.01, "ycc white");
+    }
+}
@@ -0,0 +1,318 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Antialiased shape drawing. Shapes are given in pixel coordinates,
+// where pixel (i, j) covers the unit square from (i, j) to (i+1, j+1).
+// So a filled rect from 0,0 to 10,10 covers exactly 100 pixels.
+//
+// Drawing returns a new image; the original is untouched.
+
+#[derive(Debug, Clone, Copy)]
+pub enum FillRule {
+    EvenOdd,
+    NonZero,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BlendMode {
+    Normal,
+    Add,
+    Multiply,
+    Screen,
+    Lighten,
+    Darken,
+}
+
+impl BlendMode {
+    pub fn from_name(name: &str) -> Option<BlendMode> {
+        match name {
+            "normal" => Some(BlendMode::Normal),
+            "add" => Some(BlendMode::Add),
+            "multiply" => Some(BlendMode::Multiply),
+            "screen" => Some(BlendMode::Screen),
+            "lighten" => Some(BlendMode::Lighten),
+            "darken" => Some(BlendMode::Darken),
+            _ => None,
+        }
+    }
+
+    fn blend(&self, dst: f32, src: f32) -> f32 {
+        match self {
+            BlendMode::Normal => src,
+            BlendMode::Add => dst + src,
+            BlendMode::Multiply => dst * src / 255.0,
+            BlendMode::Screen => 255.0 - (255.0 - dst) * (255.0 - src) / 255.0,
+            BlendMode::Lighten => dst.max(src),
+            BlendMode::Darken => dst.min(src),
+        }
+    }
+}
+
+pub struct Paint {
+    pub color: Pix<f32>,
+    pub blend: BlendMode,
+}
+
+// Fill rows are sampled this many times vertically. Horizontal
+// coverage is computed exactly.
+const SUBSCANLINES: usize = 8;
+
+fn seg_distance(px: f32, py: f32, p0: (f32, f32), p1: (f32, f32)) -> f32 {
+    let (dx, dy) = (p1.0 - p0.0, p1.1 - p0.1);
+    let lensq = dx * dx + dy * dy;
+    let frac = if lensq > 0.0 {
+        (((px - p0.0) * dx + (py - p0.1) * dy) / lensq).clamp(0.0, 1.0)
+    }
+    else {
+        0.0
+    };
+    (px - (p0.0 + frac * dx)).hypot(py - (p0.1 + frac * dy))
+}
+
+pub fn ellipse_points(center: (f32, f32), rx: f32, ry: f32) -> Vec<(f32, f32)> {
+    let count = ((std::f32::consts::TAU * rx.max(ry) / 2.0).ceil() as usize).clamp(16, 1024);
+    (0..count).map(|index| {
+        let theta = std::f32::consts::TAU * index as f32 / count as f32;
+        (center.0 + rx * theta.cos(), center.1 + ry * theta.sin())
+    }).collect()
+}
+
+pub fn bezier_points(pts: &[(f32, f32)]) -> Vec<(f32, f32)> {
+    // PTS is a start point followed by (control, control, end) triples.
+    let mut res: Vec<(f32, f32)> = vec![pts[0]];
+    let mut index = 1;
+    while index + 2 < pts.len() {
+        let (p0, p1, p2, p3) = (pts[index-1], pts[index], pts[index+1], pts[index+2]);
+        let hull = (p1.0-p0.0).hypot(p1.1-p0.1) + (p2.0-p1.0).hypot(p2.1-p1.1) + (p3.0-p2.0).hypot(p3.1-p2.1);
+        let count = ((hull / 2.0).ceil() as usize).clamp(4, 256);
+        for step in 1..=count {
+            let t = step as f32 / count as f32;
+            let mt = 1.0 - t;
+            let (w0, w1, w2, w3) = (mt*mt*mt, 3.0*mt*mt*t, 3.0*mt*t*t, t*t*t);
+            res.push((w0*p0.0 + w1*p1.0 + w2*p2.0 + w3*p3.0, w0*p0.1 + w1*p1.1 + w2*p2.1 + w3*p3.1));
+        }
+        index += 3;
+    }
+    res
+}
+
+impl Img<f32> {
+
+    pub fn paint_coverage(&self, xmin: usize, ymin: usize, cwidth: usize, coverage: &[f32], paint: &Paint) -> Img<f32> {
+        // Composite PAINT over a copy of self, weighted by a coverage
+        // buffer which starts at (xmin, ymin).
+        let mut res = Img {
+            filename: None,
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.clone(),
+        };
+        if cwidth == 0 {
+            return res;
+        }
+        for (index, cov) in coverage.iter().enumerate() {
+            if *cov <= 0.0 {
+                continue;
+            }
+            let cov = cov.min(1.0);
+            let (ix, jx) = (xmin + index % cwidth, ymin + index / cwidth);
+            let dst = self.at(ix, jx);
+            let pix = Pix {
+                r: dst.r + (paint.blend.blend(dst.r, paint.color.r) - dst.r) * cov,
+                g: dst.g + (paint.blend.blend(dst.g, paint.color.g) - dst.g) * cov,
+                b: dst.b + (paint.blend.blend(dst.b, paint.color.b) - dst.b) * cov,
+            };
+            res.set(ix, jx, pix);
+        }
+        res
+    }
+
+    fn clip_box(&self, x0: f32, y0: f32, x1: f32, y1: f32) -> (usize, usize, usize, usize) {
+        // Pixel range (xmin, ymin, xmax, ymax), exclusive of the max.
+        let xmin = x0.floor().clamp(0.0, self.width as f32) as usize;
+        let ymin = y0.floor().clamp(0.0, self.height as f32) as usize;
+        let xmax = x1.ceil().clamp(0.0, self.width as f32) as usize;
+        let ymax = y1.ceil().clamp(0.0, self.height as f32) as usize;
+        (xmin, ymin, xmax.max(xmin), ymax.max(ymin))
+    }
+
+    pub fn draw_path(&self, pts: &[(f32, f32)], closed: bool, width: f32, rule: FillRule, paint: &Paint) -> Img<f32> {
+        // A positive width strokes the outline; zero fills it.
+        if width > 0.0 {
+            self.stroke_path(pts, closed, width, paint)
+        }
+        else {
+            self.fill_path(pts, rule, paint)
+        }
+    }
+
+    pub fn stroke_path(&self, pts: &[(f32, f32)], closed: bool, width: f32, paint: &Paint) -> Img<f32> {
+        let halfwidth = width * 0.5;
+        let margin = halfwidth + 1.0;
+        let bx0 = pts.iter().map(|pt| pt.0).fold(f32::MAX, f32::min) - margin;
+        let by0 = pts.iter().map(|pt| pt.1).fold(f32::MAX, f32::min) - margin;
+        let bx1 = pts.iter().map(|pt| pt.0).fold(f32::MIN, f32::max) + margin;
+        let by1 = pts.iter().map(|pt| pt.1).fold(f32::MIN, f32::max) + margin;
+        let (xmin, ymin, xmax, ymax) = self.clip_box(bx0, by0, bx1, by1);
+        let cwidth = xmax - xmin;
+        let mut coverage: Vec<f32> = vec![0.0; cwidth * (ymax - ymin)];
+
+        let mut segs: Vec<((f32, f32), (f32, f32))> = pts.windows(2).map(|pair| (pair[0], pair[1])).collect();
+        if closed && pts.len() > 2 {
+            segs.push((pts[pts.len()-1], pts[0]));
+        }
+        if segs.is_empty() && !pts.is_empty() {
+            segs.push((pts[0], pts[0]));
+        }
+
+        // Each segment only touches the pixels near it; keep the max
+        // coverage so that joins don't double up.
+        for (p0, p1) in segs {
+            let (sx0, sy0, sx1, sy1) = self.clip_box(p0.0.min(p1.0) - margin, p0.1.min(p1.1) - margin, p0.0.max(p1.0) + margin, p0.1.max(p1.1) + margin);
+            for jx in sy0..sy1 {
+                for ix in sx0..sx1 {
+                    let dist = seg_distance(ix as f32 + 0.5, jx as f32 + 0.5, p0, p1);
+                    let cov = (halfwidth - dist + 0.5).clamp(0.0, 1.0);
+                    let cell = &mut coverage[(jx - ymin) * cwidth + (ix - xmin)];
+                    if cov > *cell {
+                        *cell = cov;
+                    }
+                }
+            }
+        }
+
+        self.paint_coverage(xmin, ymin, cwidth, &coverage, paint)
+    }
+
+    pub fn fill_path(&self, pts: &[(f32, f32)], rule: FillRule, paint: &Paint) -> Img<f32> {
+        let bx0 = pts.iter().map(|pt| pt.0).fold(f32::MAX, f32::min);
+        let by0 = pts.iter().map(|pt| pt.1).fold(f32::MAX, f32::min);
+        let bx1 = pts.iter().map(|pt| pt.0).fold(f32::MIN, f32::max);
+        let by1 = pts.iter().map(|pt| pt.1).fold(f32::MIN, f32::max);
+        let (xmin, ymin, xmax, ymax) = self.clip_box(bx0, by0, bx1, by1);
+        let cwidth = xmax - xmin;
+        let mut coverage: Vec<f32> = vec![0.0; cwidth * (ymax - ymin)];
+
+        let edges: Vec<((f32, f32), (f32, f32))> = (0..pts.len())
+            .map(|index| (pts[index], pts[(index+1) % pts.len()]))
+            .filter(|(p0, p1)| p0.1 != p1.1)
+            .collect();
+
+        let mut crossings: Vec<(f32, i32)> = Vec::new();
+        for jx in ymin..ymax {
+            for sub in 0..SUBSCANLINES {
+                let ypos = jx as f32 + (sub as f32 + 0.5) / SUBSCANLINES as f32;
+                crossings.clear();
+                for (p0, p1) in &edges {
+                    let (lo, hi) = if p0.1 < p1.1 { (p0.1, p1.1) } else { (p1.1, p0.1) };
+                    if ypos < lo || ypos >= hi {
+                        continue;
+                    }
+                    let xpos = p0.0 + (ypos - p0.1) * (p1.0 - p0.0) / (p1.1 - p0.1);
+                    crossings.push((xpos, if p1.1 > p0.1 { 1 } else { -1 }));
+                }
+                crossings.sort_by(|c1, c2| c1.0.total_cmp(&c2.0));
+
+                let mut winding = 0;
+                for index in 0..crossings.len() {
+                    winding = match rule {
+                        FillRule::EvenOdd => winding ^ 1,
+                        FillRule::NonZero => winding + crossings[index].1,
+                    };
+                    if winding == 0 || index+1 >= crossings.len() {
+                        continue;
+                    }
+                    // The span from this crossing to the next is inside.
+                    let xa = crossings[index].0.max(xmin as f32);
+                    let xb = crossings[index+1].0.min(xmax as f32);
+                    if xb <= xa {
+                        continue;
+                    }
+                    let row = &mut coverage[(jx - ymin) * cwidth..(jx - ymin + 1) * cwidth];
+                    for ix in (xa.floor() as usize)..(xb.ceil() as usize).min(xmax) {
+                        let overlap = xb.min(ix as f32 + 1.0) - xa.max(ix as f32);
+                        if overlap > 0.0 {
+                            row[ix - xmin] += overlap / SUBSCANLINES as f32;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.paint_coverage(xmin, ymin, cwidth, &coverage, paint)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Paint = Paint { color: Pix { r: 255.0, g: 255.0, b: 255.0 }, blend: BlendMode::Normal };
+
+    fn total(img: &Img<f32>) -> f32 {
+        img.pixels.iter().map(|pix| pix.r).sum::<f32>() / 255.0
+    }
+
+    #[test]
+    fn fill_rect_exact() {
+        let img = Img::new(10, 10);
+        let rect = [(2.0, 2.0), (6.0, 2.0), (6.0, 6.0), (2.0, 6.0)];
+        let res = img.fill_path(&rect, FillRule::NonZero, &WHITE);
+        for jx in 0..10 {
+            for ix in 0..10 {
+                let inside = (2..6).contains(&ix) && (2..6).contains(&jx);
+                assert_eq!(res.at(ix, jx).r, if inside { 255.0 } else { 0.0 }, "{} {}", ix, jx);
+            }
+        }
+        // The source image is left alone.
+        assert_eq!(total(&img), 0.0);
+
+        let half = [(0.0, 0.0), (2.5, 0.0), (2.5, 1.0), (0.0, 1.0)];
+        let res = img.fill_path(&half, FillRule::NonZero, &WHITE);
+        assert!((res.at(2, 0).r - 127.5).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn fill_rules() {
+        // A pentagram: its centre winds twice, so only nonzero fills it.
+        let star: Vec<(f32, f32)> = (0..5).map(|index| {
+            let theta = std::f32::consts::TAU * (index * 2) as f32 / 5.0;
+            (10.0 + 9.0 * theta.sin(), 10.0 - 9.0 * theta.cos())
+        }).collect();
+        let img = Img::new(20, 20);
+        assert_eq!(img.fill_path(&star, FillRule::NonZero, &WHITE).at(10, 10).r, 255.0);
+        assert_eq!(img.fill_path(&star, FillRule::EvenOdd, &WHITE).at(10, 10).r, 0.0);
+    }
+
+    #[test]
+    fn stroke_and_ellipse() {
+        let img = Img::new(10, 10);
+        let res = img.stroke_path(&[(0.0, 5.0), (10.0, 5.0)], false, 2.0, &WHITE);
+        assert_eq!((res.at(3, 3).r, res.at(3, 4).r, res.at(3, 5).r, res.at(3, 6).r), (0.0, 255.0, 255.0, 0.0));
+
+        let img = Img::new(40, 40);
+        let circle = ellipse_points((20.0, 20.0), 12.0, 12.0);
+        let area = total(&img.fill_path(&circle, FillRule::NonZero, &WHITE));
+        assert!((area - std::f32::consts::PI * 144.0).abs() < 3.0, "{}", area);
+    }
+
+    #[test]
+    fn blend_modes() {
+        let img = Img::new_constant(4, 4, Pix::grey(100.0));
+        let square = [(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)];
+        let paint = |blend| Paint { color: Pix::grey(100.0), blend };
+        assert_eq!(img.fill_path(&square, FillRule::NonZero, &paint(BlendMode::Add)).at(1, 1).r, 200.0);
+        assert_eq!(img.fill_path(&square, FillRule::NonZero, &paint(BlendMode::Darken)).at(1, 1).r, 100.0);
+        let res = img.fill_path(&square, FillRule::NonZero, &paint(BlendMode::Multiply));
+        assert!((res.at(1, 1).r - 100.0 * 100.0 / 255.0).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn bezier_ends() {
+        let pts = bezier_points(&[(0.0, 0.0), (0.0, 10.0), (10.0, 10.0), (10.0, 0.0)]);
+        assert_eq!(pts.first(), Some(&(0.0, 0.0)));
+        assert_eq!(pts.last(), Some(&(10.0, 0.0)));
+        assert!(pts.iter().all(|pt| pt.1 <= 7.5 + 1.0e-4));
+    }
+}
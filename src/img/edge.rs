@@ -0,0 +1,251 @@
+use std::f32::consts::PI;
+
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Edge detection. These work on the luma of the image and return grey
+// masks in the range 0-1, ready for interpolate.
+
+// Canny smooths the image with this Gaussian before looking for edges.
+const CANNY_SIGMA: f32 = 1.4;
+
+#[derive(Debug, Clone, Copy)]
+pub enum GradientKernel {
+    Sobel,
+    Scharr,
+}
+
+impl GradientKernel {
+    fn weights(&self) -> (f32, f32) {
+        // (corner, middle) weights of the 3x3 derivative kernel.
+        match self {
+            GradientKernel::Sobel => (1.0, 2.0),
+            GradientKernel::Scharr => (3.0, 10.0),
+        }
+    }
+}
+
+struct Grey {
+    width: usize,
+    height: usize,
+    vals: Vec<f32>,
+}
+
+impl Grey {
+    fn luma(img: &Img<f32>) -> Grey {
+        Grey {
+            width: img.width,
+            height: img.height,
+            vals: img.pixels.iter().map(|pix| pix.luma()).collect(),
+        }
+    }
+
+    fn at(&self, xpos: i32, ypos: i32) -> f32 {
+        let xp = xpos.clamp(0, self.width as i32 - 1) as usize;
+        let yp = ypos.clamp(0, self.height as i32 - 1) as usize;
+        self.vals[yp * self.width + xp]
+    }
+
+    fn gradient(&self, kernel: GradientKernel) -> (Vec<f32>, Vec<f32>) {
+        // Returns (gx, gy), scaled so that a sharp step from 0 to 255
+        // has magnitude 1.
+        let (corner, middle) = kernel.weights();
+        let norm = (2.0 * corner + middle) * 255.0;
+        let mut gxs: Vec<f32> = Vec::with_capacity(self.vals.len());
+        let mut gys: Vec<f32> = Vec::with_capacity(self.vals.len());
+        for jx in 0..self.height as i32 {
+            for ix in 0..self.width as i32 {
+                let gx = corner * (self.at(ix+1, jx-1) - self.at(ix-1, jx-1))
+                    + middle * (self.at(ix+1, jx) - self.at(ix-1, jx))
+                    + corner * (self.at(ix+1, jx+1) - self.at(ix-1, jx+1));
+                let gy = corner * (self.at(ix-1, jx+1) - self.at(ix-1, jx-1))
+                    + middle * (self.at(ix, jx+1) - self.at(ix, jx-1))
+                    + corner * (self.at(ix+1, jx+1) - self.at(ix+1, jx-1));
+                gxs.push(gx / norm);
+                gys.push(gy / norm);
+            }
+        }
+        (gxs, gys)
+    }
+
+    fn to_img(&self) -> Img<f32> {
+        let mut res = Img::new(self.width, self.height);
+        for (pix, val) in res.pixels.iter_mut().zip(&self.vals) {
+            *pix = Pix::grey(*val);
+        }
+        res
+    }
+}
+
+impl Img<f32> {
+
+    pub fn edge_gradient(&self, kernel: GradientKernel) -> (Img<f32>, Img<f32>) {
+        // Returns (magnitude, direction). Direction maps the angle
+        // -pi..pi to 0..1, with 0.5 meaning "brighter to the right".
+        let grey = Grey::luma(self);
+        let (gxs, gys) = grey.gradient(kernel);
+        let magnitude = Grey {
+            width: self.width,
+            height: self.height,
+            vals: gxs.iter().zip(&gys).map(|(gx, gy)| gx.hypot(*gy).min(1.0)).collect(),
+        };
+        let direction = Grey {
+            width: self.width,
+            height: self.height,
+            vals: gxs.iter().zip(&gys).map(|(gx, gy)| (gy.atan2(*gx) + PI) / (2.0 * PI)).collect(),
+        };
+        (magnitude.to_img(), direction.to_img())
+    }
+
+    pub fn laplacian(&self) -> Img<f32> {
+        // Absolute value of the 4-neighbour Laplacian; an isolated
+        // white pixel on black gives 1.
+        let grey = Grey::luma(self);
+        let mut res = Grey { width: self.width, height: self.height, vals: Vec::with_capacity(self.pixcount()) };
+        for jx in 0..self.height as i32 {
+            for ix in 0..self.width as i32 {
+                let lap = grey.at(ix-1, jx) + grey.at(ix+1, jx) + grey.at(ix, jx-1) + grey.at(ix, jx+1) - 4.0 * grey.at(ix, jx);
+                res.vals.push((lap.abs() / (4.0 * 255.0)).min(1.0));
+            }
+        }
+        res.to_img()
+    }
+
+    pub fn canny(&self, low: f32, high: f32) -> Img<f32> {
+        // Smooth, take the Sobel gradient, thin edges to local maxima
+        // along the gradient, then keep pixels above HIGH plus any
+        // connected pixels above LOW. LOW and HIGH are magnitudes on the
+        // same 0-1 scale as sobel. Edges are 1, everything else 0.
+        let grey = Grey::luma(&self.gaussblur(CANNY_SIGMA));
+        let (width, height) = (self.width, self.height);
+        let (gxs, gys) = grey.gradient(GradientKernel::Sobel);
+        let mags: Vec<f32> = gxs.iter().zip(&gys).map(|(gx, gy)| gx.hypot(*gy)).collect();
+        let magat = |ix: i32, jx: i32| -> f32 {
+            if ix < 0 || jx < 0 || ix >= width as i32 || jx >= height as i32 {
+                0.0
+            }
+            else {
+                mags[jx as usize * width + ix as usize]
+            }
+        };
+
+        let mut thin: Vec<f32> = vec![0.0; mags.len()];
+        for jx in 0..height {
+            for ix in 0..width {
+                let index = jx * width + ix;
+                let mag = mags[index];
+                if mag <= 0.0 {
+                    continue;
+                }
+                // Compare against the two neighbours along the gradient,
+                // with the angle rounded to 45 degrees.
+                let angle = gys[index].atan2(gxs[index]).to_degrees().rem_euclid(180.0);
+                let (dx, dy) = if !(22.5..157.5).contains(&angle) {
+                    (1, 0)
+                }
+                else if angle < 67.5 {
+                    (1, 1)
+                }
+                else if angle < 112.5 {
+                    (0, 1)
+                }
+                else {
+                    (-1, 1)
+                };
+                let (ixs, jxs) = (ix as i32, jx as i32);
+                if mag >= magat(ixs + dx, jxs + dy) && mag >= magat(ixs - dx, jxs - dy) {
+                    thin[index] = mag;
+                }
+            }
+        }
+
+        let mut edges: Vec<bool> = vec![false; thin.len()];
+        let mut pending: Vec<usize> = Vec::new();
+        for (index, mag) in thin.iter().enumerate() {
+            if *mag > 0.0 && *mag >= high {
+                edges[index] = true;
+                pending.push(index);
+            }
+        }
+        while let Some(index) = pending.pop() {
+            let (ix, jx) = ((index % width) as i32, (index / width) as i32);
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (nx, ny) = (ix + dx, jx + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                        continue;
+                    }
+                    let nindex = ny as usize * width + nx as usize;
+                    if !edges[nindex] && thin[nindex] > 0.0 && thin[nindex] >= low {
+                        edges[nindex] = true;
+                        pending.push(nindex);
+                    }
+                }
+            }
+        }
+
+        let res = Grey {
+            width,
+            height,
+            vals: edges.iter().map(|edge| if *edge { 1.0 } else { 0.0 }).collect(),
+        };
+        res.to_img()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step_img(width: usize, height: usize) -> Img<f32> {
+        // Black on the left half, white on the right.
+        let mut img = Img::new(width, height);
+        for jx in 0..height {
+            for ix in width/2..width {
+                img.set(ix, jx, Pix::grey(255.0));
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn gradient_step_edge() {
+        let img = step_img(8, 5);
+        for kernel in [GradientKernel::Sobel, GradientKernel::Scharr] {
+            let (mag, dir) = img.edge_gradient(kernel);
+            for jx in 0..5 {
+                for ix in 0..8 {
+                    let want = if ix == 3 || ix == 4 { 1.0 } else { 0.0 };
+                    assert!((mag.at(ix, jx).r - want).abs() < 1.0e-6, "{:?} {} {}", kernel, ix, jx);
+                }
+                assert_eq!(dir.at(3, jx).r, 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn laplacian_spot() {
+        let mut img = Img::new(5, 5);
+        img.set(2, 2, Pix::grey(255.0));
+        let res = img.laplacian();
+        assert_eq!(res.at(2, 2).r, 1.0);
+        assert_eq!(res.at(1, 2).r, 0.25);
+        assert_eq!(res.at(1, 1).r, 0.0);
+    }
+
+    #[test]
+    fn canny_step_edge() {
+        let res = step_img(16, 9).canny(0.1, 0.3);
+        for jx in 0..9 {
+            let cols: Vec<usize> = (0..16).filter(|ix| res.at(*ix, jx).r == 1.0).collect();
+            assert!(!cols.is_empty() && cols.iter().all(|ix| (7..=8).contains(ix)), "row {}: {:?}", jx, cols);
+        }
+        assert!(res.pixels.iter().all(|pix| pix.r == 0.0 || pix.r == 1.0));
+        // Nothing passes a threshold above the strongest edge, and a flat
+        // image has no edges at all.
+        assert!(step_img(16, 9).canny(0.1, 2.0).pixels.iter().all(|pix| pix.r == 0.0));
+        let flat = Img::new_constant(6, 6, Pix::grey(90.0));
+        assert!(flat.canny(0.1, 0.3).pixels.iter().all(|pix| pix.r == 0.0));
+    }
+}
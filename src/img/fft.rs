@@ -0,0 +1,330 @@
+use std::f64::consts::PI;
+
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Two-dimensional discrete Fourier transform, channel by channel.
+//
+// The 1D transform is a recursive mixed-radix Cooley-Tukey: split by
+// the smallest prime factor of the length, transform the pieces, and
+// recombine. A large prime length is turned into a power-of-two
+// convolution (Bluestein's chirp-z trick), so every size is
+// O(n log n).
+//
+// Spectra are stored "centred": the zero frequency sits at pixel
+// (width/2, height/2). Coefficients are divided by the pixel count, so
+// the centre of a magnitude image is the average channel value.
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    fn expi(theta: f64) -> Complex {
+        Complex { re: theta.cos(), im: theta.sin() }
+    }
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+
+    fn scale(self, val: f64) -> Complex {
+        Complex::new(self.re * val, self.im * val)
+    }
+
+    fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+}
+
+// Primes up to this length use a direct DFT; beyond it, Bluestein.
+const DIRECT_DFT_MAX: usize = 64;
+
+fn smallest_factor(num: usize) -> usize {
+    let mut factor = 2;
+    while factor * factor <= num {
+        if num.is_multiple_of(factor) {
+            return factor;
+        }
+        factor += 1;
+    }
+    num
+}
+
+fn fft_1d(data: &[Complex], inverse: bool) -> Vec<Complex> {
+    // Unnormalized in both directions.
+    let count = data.len();
+    if count <= 1 {
+        return data.to_vec();
+    }
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let radix = smallest_factor(count);
+    let sublen = count / radix;
+
+    if sublen == 1 && count > DIRECT_DFT_MAX {
+        return bluestein(data, inverse);
+    }
+    if sublen == 1 {
+        // Small prime length: direct DFT.
+        return (0..count).map(|freq| {
+            data.iter().enumerate().fold(Complex::default(), |acc, (index, val)| {
+                let theta = sign * 2.0 * PI * ((freq * index) % count) as f64 / count as f64;
+                acc.add(val.mul(Complex::expi(theta)))
+            })
+        }).collect();
+    }
+
+    let subs: Vec<Vec<Complex>> = (0..radix).map(|offset| {
+        let part: Vec<Complex> = data.iter().skip(offset).step_by(radix).copied().collect();
+        fft_1d(&part, inverse)
+    }).collect();
+
+    let mut res: Vec<Complex> = vec![Complex::default(); count];
+    for (freq, val) in res.iter_mut().enumerate() {
+        // The twiddle for piece OFFSET is step^offset.
+        let step = Complex::expi(sign * 2.0 * PI * freq as f64 / count as f64);
+        let mut twiddle = Complex::new(1.0, 0.0);
+        let mut total = Complex::default();
+        for sub in &subs {
+            total = total.add(sub[freq % sublen].mul(twiddle));
+            twiddle = twiddle.mul(step);
+        }
+        *val = total;
+    }
+    res
+}
+
+fn bluestein(data: &[Complex], inverse: bool) -> Vec<Complex> {
+    // X[k] = c[k] * sum(x[n] c[n] conj(c[k-n])), with the chirp
+    // c[n] = exp(-i pi n^2 / N). The sum is a convolution, done with
+    // power-of-two transforms.
+    let count = data.len();
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let chirp: Vec<Complex> = (0..count).map(|index| {
+        let phase = (index * index) % (2 * count);
+        Complex::expi(sign * PI * phase as f64 / count as f64)
+    }).collect();
+    let padlen = (2 * count - 1).next_power_of_two();
+    let mut avals: Vec<Complex> = vec![Complex::default(); padlen];
+    let mut bvals: Vec<Complex> = vec![Complex::default(); padlen];
+    for index in 0..count {
+        avals[index] = data[index].mul(chirp[index]);
+        bvals[index] = chirp[index].conj();
+        if index > 0 {
+            bvals[padlen - index] = chirp[index].conj();
+        }
+    }
+    let afreq = fft_1d(&avals, false);
+    let bfreq = fft_1d(&bvals, false);
+    let product: Vec<Complex> = afreq.iter().zip(&bfreq).map(|(v1, v2)| v1.mul(*v2)).collect();
+    let conv = fft_1d(&product, true);
+    (0..count).map(|index| chirp[index].mul(conv[index]).scale(1.0 / padlen as f64)).collect()
+}
+
+struct Spectrum {
+    width: usize,
+    height: usize,
+    vals: Vec<Complex>,
+}
+
+impl Spectrum {
+    fn transform(&self, inverse: bool) -> Spectrum {
+        let (width, height) = (self.width, self.height);
+        let mut vals = self.vals.clone();
+        for jx in 0..height {
+            let row = fft_1d(&vals[jx * width..(jx+1) * width], inverse);
+            vals[jx * width..(jx+1) * width].copy_from_slice(&row);
+        }
+        let mut column: Vec<Complex> = vec![Complex::default(); height];
+        for ix in 0..width {
+            for (jx, val) in column.iter_mut().enumerate() {
+                *val = vals[jx * width + ix];
+            }
+            let col = fft_1d(&column, inverse);
+            for (jx, val) in col.iter().enumerate() {
+                vals[jx * width + ix] = *val;
+            }
+        }
+        Spectrum { width, height, vals }
+    }
+
+    fn shifted(&self, forward: bool) -> Spectrum {
+        // Move the zero frequency to the centre (FORWARD), or back to
+        // the corner.
+        let (width, height) = (self.width, self.height);
+        let mut vals: Vec<Complex> = vec![Complex::default(); self.vals.len()];
+        for jx in 0..height {
+            for ix in 0..width {
+                let (cx, cy) = ((ix + width / 2) % width, (jx + height / 2) % height);
+                if forward {
+                    vals[cy * width + cx] = self.vals[jx * width + ix];
+                }
+                else {
+                    vals[jx * width + ix] = self.vals[cy * width + cx];
+                }
+            }
+        }
+        Spectrum { width, height, vals }
+    }
+}
+
+fn channel(pix: &Pix<f32>, chan: usize) -> f32 {
+    match chan {
+        0 => pix.r,
+        1 => pix.g,
+        _ => pix.b,
+    }
+}
+
+fn pair_to_img(width: usize, height: usize, chans: &[Vec<(f32, f32)>; 3], which: usize) -> Img<f32> {
+    let mut res = Img::new(width, height);
+    for (index, pix) in res.pixels.iter_mut().enumerate() {
+        let pick = |chan: &Vec<(f32, f32)>| if which == 0 { chan[index].0 } else { chan[index].1 };
+        *pix = Pix::new(pick(&chans[0]), pick(&chans[1]), pick(&chans[2]));
+    }
+    res
+}
+
+impl Img<f32> {
+
+    fn forward_spectra(&self) -> [Spectrum; 3] {
+        let count = self.pixcount() as f64;
+        [0, 1, 2].map(|chan| {
+            let spec = Spectrum {
+                width: self.width,
+                height: self.height,
+                vals: self.pixels.iter().map(|pix| Complex::new(channel(pix, chan) as f64 / count, 0.0)).collect(),
+            };
+            spec.transform(false).shifted(true)
+        })
+    }
+
+    fn inverse_spectra(specs: [Spectrum; 3]) -> Img<f32> {
+        // The forward transform already divided by the pixel count.
+        let (width, height) = (specs[0].width, specs[0].height);
+        let outs = specs.map(|spec| spec.shifted(false).transform(true));
+        let mut res = Img::new(width, height);
+        for (index, pix) in res.pixels.iter_mut().enumerate() {
+            let val = |chan: usize| outs[chan].vals[index].re as f32;
+            *pix = Pix::new(val(0), val(1), val(2));
+        }
+        res
+    }
+
+    pub fn fft(&self, polar: bool) -> (Img<f32>, Img<f32>) {
+        // Returns (magnitude, phase) if POLAR, else (real, imaginary).
+        let specs = self.forward_spectra();
+        let chans: [Vec<(f32, f32)>; 3] = specs.map(|spec| {
+            spec.vals.iter().map(|val| {
+                if polar {
+                    (val.re.hypot(val.im) as f32, val.im.atan2(val.re) as f32)
+                }
+                else {
+                    (val.re as f32, val.im as f32)
+                }
+            }).collect()
+        });
+        (pair_to_img(self.width, self.height, &chans, 0), pair_to_img(self.width, self.height, &chans, 1))
+    }
+
+    pub fn ifft(first: &Img<f32>, second: &Img<f32>, polar: bool) -> Img<f32> {
+        // The inverse of fft; the two images must be the same size.
+        let specs = [0, 1, 2].map(|chan| Spectrum {
+            width: first.width,
+            height: first.height,
+            vals: first.pixels.iter().zip(&second.pixels).map(|(p1, p2)| {
+                let (v1, v2) = (channel(p1, chan) as f64, channel(p2, chan) as f64);
+                if polar {
+                    Complex::expi(v2).scale(v1)
+                }
+                else {
+                    Complex::new(v1, v2)
+                }
+            }).collect(),
+        });
+        Img::inverse_spectra(specs)
+    }
+
+    pub fn fft_filter(&self, mask: &Img<f32>) -> Img<f32> {
+        // Multiply the centred spectrum by MASK, channel by channel, and
+        // transform back. Like every other mask, it runs from 0 to 1: 1
+        // passes a frequency unchanged and 0 removes it.
+        let specs = self.forward_spectra();
+        let mut index: usize = 0;
+        let filtered = specs.map(|mut spec| {
+            for (val, pix) in spec.vals.iter_mut().zip(&mask.pixels) {
+                *val = val.scale(channel(pix, index) as f64);
+            }
+            index += 1;
+            spec
+        });
+        Img::inverse_spectra(filtered)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_img(width: usize, height: usize) -> Img<f32> {
+        let mut img = Img::new(width, height);
+        for jx in 0..height {
+            for ix in 0..width {
+                let val = ((ix * 37 + jx * 91) % 256) as f32;
+                img.set(ix, jx, Pix::new(val, 255.0 - val, (ix * 20) as f32));
+            }
+        }
+        img
+    }
+
+    fn assert_same(img1: &Img<f32>, img2: &Img<f32>) {
+        for (p1, p2) in img1.pixels.iter().zip(&img2.pixels) {
+            let diff = (p1.r - p2.r).abs().max((p1.g - p2.g).abs()).max((p1.b - p2.b).abs());
+            assert!(diff < 1.0e-2, "{:?} vs {:?}", p1, p2);
+        }
+    }
+
+    #[test]
+    fn fft_round_trip() {
+        // Even, odd and large-prime (Bluestein) sizes.
+        for (width, height) in [(8, 6), (5, 9), (67, 3)] {
+            let img = sample_img(width, height);
+            for polar in [true, false] {
+                let (first, second) = img.fft(polar);
+                assert_same(&img, &Img::ifft(&first, &second, polar));
+            }
+        }
+    }
+
+    #[test]
+    fn fft_centre_is_average() {
+        let img = sample_img(8, 6);
+        let (mag, _) = img.fft(true);
+        let avg = img.average();
+        let centre = mag.at(4, 3);
+        assert!((centre.r - avg.r).abs() < 1.0e-3 && (centre.g - avg.g).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn fft_filter_full_mask() {
+        let img = sample_img(12, 7);
+        let mut mask = Img::new(12, 7);
+        for pix in mask.pixels.iter_mut() {
+            *pix = Pix::new(1.0, 1.0, 1.0);
+        }
+        assert_same(&img, &img.fft_filter(&mask));
+        let black = img.fft_filter(&Img::new(12, 7));
+        assert!(black.pixels.iter().all(|pix| pix.r.abs() < 1.0e-3));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+#[derive(Debug, Clone)]
+pub struct ColorStop {
+    pub pos: f32,
+    pub color: Pix<f32>,
+}
+
+pub struct Gradient {
+    stops: Vec<ColorStop>,
+    linear: bool,
+}
+
+impl Gradient {
+    pub fn new(stops: &[ColorStop], linear: bool) -> Gradient {
+        // Stops are sorted by position. In linear mode the colors are
+        // interpolated in linear light, so they're converted up front.
+        let mut ls: Vec<ColorStop> = stops.iter()
+            .map(|stop| ColorStop {
+                pos: stop.pos,
+                color: if linear { stop.color.to_linear() } else { stop.color.clone() },
+            })
+            .collect();
+        ls.sort_by(|s1, s2| s1.pos.total_cmp(&s2.pos));
+        Gradient { stops: ls, linear }
+    }
+
+    pub fn at(&self, pos: f32) -> Pix<f32> {
+        let first = &self.stops[0];
+        let last = &self.stops[self.stops.len()-1];
+        let pix = if pos.is_nan() || pos <= first.pos {
+            first.color.clone()
+        }
+        else if pos >= last.pos {
+            last.color.clone()
+        }
+        else {
+            let index = self.stops.iter().position(|stop| stop.pos > pos).unwrap_or(self.stops.len()-1);
+            let s0 = &self.stops[index-1];
+            let s1 = &self.stops[index];
+            let span = s1.pos - s0.pos;
+            let frac = if span > 0.0 { (pos - s0.pos) / span } else { 1.0 };
+            lerp_pix(&s0.color, &s1.color, frac)
+        };
+        if self.linear { pix.to_srgb() } else { pix }
+    }
+}
+
+fn lerp_pix(pix0: &Pix<f32>, pix1: &Pix<f32>, frac: f32) -> Pix<f32> {
+    Pix {
+        r: pix0.r + (pix1.r - pix0.r) * frac,
+        g: pix0.g + (pix1.g - pix0.g) * frac,
+        b: pix0.b + (pix1.b - pix0.b) * frac,
+    }
+}
+
+impl Img<f32> {
+
+    pub fn linear_gradient(width: usize, height: usize, start: (f32, f32), end: (f32, f32), grad: &Gradient) -> Img<f32> {
+        // Position 0 at START, 1 at END, constant along perpendiculars.
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        let lensq = dx * dx + dy * dy;
+        let mut res = Img::new(width, height);
+        for jx in 0..height {
+            for ix in 0..width {
+                let (px, py) = (ix as f32 - start.0, jx as f32 - start.1);
+                let pos = if lensq > 0.0 { (px * dx + py * dy) / lensq } else { 0.0 };
+                res.set(ix, jx, grad.at(pos));
+            }
+        }
+        res
+    }
+
+    pub fn radial_gradient(width: usize, height: usize, center: (f32, f32), rad: f32, grad: &Gradient) -> Img<f32> {
+        // Position 0 at CENTER, 1 at distance RAD.
+        let mut res = Img::new(width, height);
+        for jx in 0..height {
+            for ix in 0..width {
+                let dist = (ix as f32 - center.0).hypot(jx as f32 - center.1);
+                res.set(ix, jx, grad.at(dist / rad));
+            }
+        }
+        res
+    }
+
+    pub fn conic_gradient(width: usize, height: usize, center: (f32, f32), angle: f32, grad: &Gradient) -> Img<f32> {
+        // Position runs from 0 to 1 clockwise around CENTER, starting at
+        // ANGLE (radians, zero pointing right).
+        let mut res = Img::new(width, height);
+        for jx in 0..height {
+            for ix in 0..width {
+                let theta = (jx as f32 - center.1).atan2(ix as f32 - center.0);
+                let pos = ((theta - angle) / std::f32::consts::TAU).rem_euclid(1.0);
+                res.set(ix, jx, grad.at(pos));
+            }
+        }
+        res
+    }
+
+    pub fn corner_gradient(width: usize, height: usize, corners: &[Pix<f32>; 4], linear: bool) -> Img<f32> {
+        // Bilinear blend of four corner colors: top-left, top-right,
+        // bottom-left, bottom-right.
+        let cols: Vec<Pix<f32>> = corners.iter()
+            .map(|pix| if linear { pix.to_linear() } else { pix.clone() })
+            .collect();
+        let xscale = if width > 1 { (width-1) as f32 } else { 1.0 };
+        let yscale = if height > 1 { (height-1) as f32 } else { 1.0 };
+        let mut res = Img::new(width, height);
+        for jx in 0..height {
+            let yfrac = jx as f32 / yscale;
+            let left = lerp_pix(&cols[0], &cols[2], yfrac);
+            let right = lerp_pix(&cols[1], &cols[3], yfrac);
+            for ix in 0..width {
+                let pix = lerp_pix(&left, &right, ix as f32 / xscale);
+                res.set(ix, jx, if linear { pix.to_srgb() } else { pix });
+            }
+        }
+        res
+    }
+
+}
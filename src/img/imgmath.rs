@@ -4,6 +4,7 @@ use crate::exec::except::ExecError;
 
 impl Img<f32> {
 
+    #[allow(clippy::needless_return)]
     pub fn average(&self) -> Pix<f32> {
         let mut total: Pix<f32> = Pix { r:0.0, g:0.0, b:0.0 };
         for val in &self.pixels {
@@ -18,6 +19,7 @@ impl Img<f32> {
         return total;
     }
 
+    #[allow(clippy::let_and_return)]
     pub fn at_lerp(&self, xpos: f32, ypos: f32) -> Pix<f32> {
         if xpos.is_nan() || ypos.is_nan() {
             return Pix::default();
@@ -55,6 +57,7 @@ impl Img<f32> {
         Ok(res)
     }
     
+    #[allow(clippy::assign_op_pattern)]
     pub fn project_shade<F>(&self, func: F) -> Img<f32>
     where F: Fn(f32, f32) -> (f32, f32, f32) {
         let mut res = Img::new(self.width, self.height);
@@ -176,6 +179,7 @@ impl Img<f32> {
         res
     }
 
+    #[allow(clippy::assign_op_pattern, clippy::let_and_return)]
     pub fn holify(&self, rad: f32) -> Img<f32> {
         let fwidth = self.width as f32;
         let fheight = self.height as f32;
@@ -261,5 +265,203 @@ impl Img<f32> {
         
         res
     }
-    
+
+    pub fn gaussblur(&self, sigma: f32) -> Img<f32> {
+        // Separable Gaussian blur, out to three sigmas. Edges are
+        // extended by clamping.
+        if sigma <= 0.0 {
+            return self.map(|pix| pix.clone());
+        }
+        let rad = (sigma * 3.0).ceil() as i32;
+        let mut kernel: Vec<f32> = (-rad..=rad)
+            .map(|diff| (-(diff as f32).powi(2) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let total: f32 = kernel.iter().sum();
+        for val in kernel.iter_mut() {
+            *val /= total;
+        }
+
+        let pass = |src: &Img<f32>, horiz: bool| -> Img<f32> {
+            let mut res = Img::new(src.width, src.height);
+            for jx in 0..src.height {
+                for ix in 0..src.width {
+                    let mut total: Pix<f32> = Pix::default();
+                    for (index, weight) in kernel.iter().enumerate() {
+                        let diff = index as i32 - rad;
+                        let pix = if horiz {
+                            src.at_clamped(ix as i32 + diff, jx as i32)
+                        }
+                        else {
+                            src.at_clamped(ix as i32, jx as i32 + diff)
+                        };
+                        total.r += pix.r * weight;
+                        total.g += pix.g * weight;
+                        total.b += pix.b * weight;
+                    }
+                    res.set(ix, jx, total);
+                }
+            }
+            res
+        };
+
+        pass(&pass(self, true), false)
+    }
+
+    pub fn median_filter(&self, rad: i32) -> Img<f32> {
+        // Square window of side 2*RAD+1, clipped at the edges. Uses
+        // Huang's sliding histogram, so each step along a row only adds
+        // and removes one column. Values are rounded to whole tones
+        // 0-255.
+        let tone = |val: f32| val.round().clamp(0.0, 255.0) as usize;
+        let mut res = Img::new(self.width, self.height);
+        let (width, height) = (self.width as i32, self.height as i32);
+        for jx in 0..height {
+            let (ymin, ymax) = (jx.saturating_sub(rad).max(0), jx.saturating_add(rad).min(height - 1));
+            let mut hists = [[0u32; 256]; 3];
+            let mut count: u32 = 0;
+            let addcolumn = |hists: &mut [[u32; 256]; 3], ix: i32, sign: i32| -> u32 {
+                for jy in ymin..=ymax {
+                    let pix = self.at(ix as usize, jy as usize);
+                    for (chan, val) in [pix.r, pix.g, pix.b].iter().enumerate() {
+                        let entry = &mut hists[chan][tone(*val)];
+                        *entry = (*entry as i32 + sign) as u32;
+                    }
+                }
+                (ymax - ymin + 1) as u32
+            };
+            for ix in 0..rad.min(width) {
+                count += addcolumn(&mut hists, ix, 1);
+            }
+            for ix in 0..width {
+                if ix.saturating_add(rad) < width {
+                    count += addcolumn(&mut hists, ix + rad, 1);
+                }
+                if ix.saturating_sub(rad) > 0 {
+                    count -= addcolumn(&mut hists, ix - rad - 1, -1);
+                }
+                let mut vals = [0.0; 3];
+                for (chan, val) in vals.iter_mut().enumerate() {
+                    let mut cumul: u32 = 0;
+                    for (index, entry) in hists[chan].iter().enumerate() {
+                        cumul += entry;
+                        if 2 * cumul > count {
+                            *val = index as f32;
+                            break;
+                        }
+                    }
+                }
+                res.set(ix as usize, jx as usize, Pix::new(vals[0], vals[1], vals[2]));
+            }
+        }
+        res
+    }
+
+    pub fn bilateral(&self, rad: i32, range: f32) -> Img<f32> {
+        // Weighted average over a (2*RAD+1)-square window. The spatial
+        // sigma is RAD/2; RANGE is the colour-difference sigma on the
+        // 0-255 scale, so pixels across a strong edge barely count.
+        let spatial = (rad as f32 * 0.5).max(0.5);
+        let mut res = Img::new(self.width, self.height);
+        for jx in 0..self.height as i32 {
+            for ix in 0..self.width as i32 {
+                let center = self.at(ix as usize, jx as usize);
+                let mut total: Pix<f32> = Pix::default();
+                let mut totalweight: f32 = 0.0;
+                for jy in jx.saturating_sub(rad).max(0)..=jx.saturating_add(rad).min(self.height as i32 - 1) {
+                    for iy in ix.saturating_sub(rad).max(0)..=ix.saturating_add(rad).min(self.width as i32 - 1) {
+                        let pix = self.at(iy as usize, jy as usize);
+                        let (dx, dy) = ((iy - ix) as f32, (jy - jx) as f32);
+                        let distsq = dx * dx + dy * dy;
+                        let (dr, dg, db) = (pix.r - center.r, pix.g - center.g, pix.b - center.b);
+                        let diffsq = dr * dr + dg * dg + db * db;
+                        let weight = (-distsq / (2.0 * spatial * spatial) - diffsq / (2.0 * range * range)).exp();
+                        totalweight += weight;
+                        total.r += pix.r * weight;
+                        total.g += pix.g * weight;
+                        total.b += pix.b * weight;
+                    }
+                }
+                res.set(ix as usize, jx as usize, Pix::new(total.r / totalweight, total.g / totalweight, total.b / totalweight));
+            }
+        }
+        res
+    }
+
+    pub fn nlmeans(&self, rad: i32, strength: f32) -> Img<f32> {
+        // Non-local means: average the pixels in a (2*RAD+1)-square
+        // search window, weighted by how much the 3x3 patch around each
+        // one resembles the patch around this pixel. STRENGTH is the
+        // filtering parameter h, on the 0-255 scale.
+        let patchdist = |ix: i32, jx: i32, iy: i32, jy: i32| -> f32 {
+            let mut total: f32 = 0.0;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let p1 = self.at_clamped(ix + dx, jx + dy);
+                    let p2 = self.at_clamped(iy + dx, jy + dy);
+                    let (dr, dg, db) = (p1.r - p2.r, p1.g - p2.g, p1.b - p2.b);
+                    total += dr * dr + dg * dg + db * db;
+                }
+            }
+            total / 27.0
+        };
+        let hsq = strength * strength;
+        let mut res = Img::new(self.width, self.height);
+        for jx in 0..self.height as i32 {
+            for ix in 0..self.width as i32 {
+                let mut total: Pix<f32> = Pix::default();
+                let mut totalweight: f32 = 0.0;
+                for jy in jx.saturating_sub(rad).max(0)..=jx.saturating_add(rad).min(self.height as i32 - 1) {
+                    for iy in ix.saturating_sub(rad).max(0)..=ix.saturating_add(rad).min(self.width as i32 - 1) {
+                        let weight = (-patchdist(ix, jx, iy, jy) / hsq).exp();
+                        let pix = self.at(iy as usize, jy as usize);
+                        totalweight += weight;
+                        total.r += pix.r * weight;
+                        total.g += pix.g * weight;
+                        total.b += pix.b * weight;
+                    }
+                }
+                res.set(ix as usize, jx as usize, Pix::new(total.r / totalweight, total.g / totalweight, total.b / totalweight));
+            }
+        }
+        res
+    }
+
+    pub fn unsharp(&self, sigma: f32, amount: f32, threshold: f32) -> Img<f32> {
+        // Add back AMOUNT times the difference from a Gaussian blur.
+        // Channel differences smaller than THRESHOLD are left alone, so
+        // that flat areas don't get their noise sharpened.
+        let blurred = self.gaussblur(sigma);
+        let sharpen = |val: f32, blur: f32| {
+            let diff = val - blur;
+            if diff.abs() < threshold { val } else { val + amount * diff }
+        };
+        self.combine(&blurred, |pix, blur| Pix {
+            r: sharpen(pix.r, blur.r),
+            g: sharpen(pix.g, blur.g),
+            b: sharpen(pix.b, blur.b),
+        })
+    }
+
+    pub fn highpass(&self, sigma: f32) -> Img<f32> {
+        // The difference from a Gaussian blur, centred on mid-grey.
+        let blurred = self.gaussblur(sigma);
+        self.combine(&blurred, |pix, blur| Pix {
+            r: pix.r - blur.r + 127.5,
+            g: pix.g - blur.g + 127.5,
+            b: pix.b - blur.b + 127.5,
+        })
+    }
+
+    pub fn flatten(&self, rad: i32) -> Img<f32> {
+        // Keep the detail smaller than RAD, but replace the broad
+        // background with its average color. (This was degrad.imp.)
+        let blurred = self.taxiblur(rad);
+        let avpix = blurred.average();
+        self.combine(&blurred, |pix, blur| Pix {
+            r: pix.r - blur.r + avpix.r,
+            g: pix.g - blur.g + avpix.g,
+            b: pix.b - blur.b + avpix.b,
+        })
+    }
+
 }
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Grey-scale morphology, channel by channel. Pixels outside the image
+// are ignored (they never win the min or max). Dilation uses the
+// reflected element, as usual, so open and close are true openings and
+// closings even for lopsided elements.
+//
+// The 1D min/max filters use the van Herk/Gil-Werman algorithm, which
+// costs about three comparisons per pixel whatever the window length.
+// Square elements are separable; a cross is a horizontal line unioned
+// with a vertical one; discs and image elements are split into
+// horizontal runs, one per row.
+
+#[derive(Debug, Clone, Copy)]
+pub enum MorphOp {
+    Erode,
+    Dilate,
+}
+
+impl MorphOp {
+    fn identity(&self) -> f32 {
+        match self {
+            MorphOp::Erode => f32::INFINITY,
+            MorphOp::Dilate => f32::NEG_INFINITY,
+        }
+    }
+
+    fn apply(&self, v1: f32, v2: f32) -> f32 {
+        match self {
+            MorphOp::Erode => v1.min(v2),
+            MorphOp::Dilate => v1.max(v2),
+        }
+    }
+}
+
+pub enum StructElem {
+    Square(i32),
+    Disc(i32),
+    Cross(i32),
+    // Offsets from the centre, grouped as horizontal runs:
+    // (dy, x0, x1) covers (x0..=x1, dy).
+    Runs(Vec<(i32, i32, i32)>),
+}
+
+impl StructElem {
+    pub fn from_name(name: &str, rad: i32) -> Option<StructElem> {
+        match name {
+            "square" => Some(StructElem::Square(rad)),
+            "disc" => Some(StructElem::Disc(rad)),
+            "cross" => Some(StructElem::Cross(rad)),
+            _ => None,
+        }
+    }
+
+    pub fn from_img(img: &Img<f32>) -> StructElem {
+        // Pixels with luma of at least 0.5 are in the element (so both
+        // 0-1 masks and 0-255 images work). The centre is the middle
+        // pixel, rounding down.
+        let (cx, cy) = ((img.width / 2) as i32, (img.height / 2) as i32);
+        let mut runs: Vec<(i32, i32, i32)> = Vec::new();
+        for jx in 0..img.height {
+            let mut start: Option<usize> = None;
+            for ix in 0..=img.width {
+                let inside = ix < img.width && img.at(ix, jx).luma() >= 0.5;
+                match (inside, start) {
+                    (true, None) => { start = Some(ix); },
+                    (false, Some(sx)) => {
+                        runs.push((jx as i32 - cy, sx as i32 - cx, ix as i32 - 1 - cx));
+                        start = None;
+                    },
+                    _ => {},
+                }
+            }
+        }
+        StructElem::Runs(runs)
+    }
+
+    fn reflected(&self) -> StructElem {
+        // The element turned through 180 degrees. Dilation uses this,
+        // so that opening and closing hold for asymmetric elements.
+        match self {
+            StructElem::Square(rad) => StructElem::Square(*rad),
+            StructElem::Disc(rad) => StructElem::Disc(*rad),
+            StructElem::Cross(rad) => StructElem::Cross(*rad),
+            StructElem::Runs(runs) => StructElem::Runs(runs.iter().map(|(dy, x0, x1)| (-dy, -x1, -x0)).collect()),
+        }
+    }
+
+    fn disc_runs(rad: i32) -> Vec<(i32, i32, i32)> {
+        (-rad..=rad).map(|dy| {
+            let half = (((rad * rad - dy * dy) as f32).sqrt() + 0.5).floor() as i32;
+            (dy, -half, half)
+        }).collect()
+    }
+}
+
+fn sliding(src: &[f32], start: i32, len: usize, op: MorphOp, out: &mut [f32]) {
+    // out[i] = op over src[i+start .. i+start+len], ignoring indices
+    // outside src. The van Herk/Gil-Werman block trick: a forward
+    // running op within each block of LEN, a backward one, and each
+    // window straddles one block boundary.
+    let count = src.len();
+    let padlen = count + len - 1;
+    let padded: Vec<f32> = (0..padlen).map(|index| {
+        let pos = index as i32 + start;
+        if pos >= 0 && (pos as usize) < count { src[pos as usize] } else { op.identity() }
+    }).collect();
+    let mut forward: Vec<f32> = padded.clone();
+    let mut backward: Vec<f32> = padded.clone();
+    for index in 1..padlen {
+        if !index.is_multiple_of(len) {
+            forward[index] = op.apply(forward[index-1], padded[index]);
+        }
+    }
+    for index in (0..padlen.saturating_sub(1)).rev() {
+        if !(index + 1).is_multiple_of(len) {
+            backward[index] = op.apply(backward[index+1], padded[index]);
+        }
+    }
+    for (index, val) in out.iter_mut().enumerate().take(count) {
+        *val = op.apply(backward[index], forward[index + len - 1]);
+    }
+}
+
+struct Plane {
+    width: usize,
+    height: usize,
+    vals: Vec<f32>,
+}
+
+impl Plane {
+    fn horizontal(&self, x0: i32, x1: i32, op: MorphOp) -> Plane {
+        let mut vals: Vec<f32> = vec![0.0; self.vals.len()];
+        let len = (x1 - x0 + 1) as usize;
+        for jx in 0..self.height {
+            let row = jx * self.width..(jx+1) * self.width;
+            sliding(&self.vals[row.clone()], x0, len, op, &mut vals[row]);
+        }
+        Plane { width: self.width, height: self.height, vals }
+    }
+
+    fn vertical(&self, y0: i32, y1: i32, op: MorphOp) -> Plane {
+        let mut vals: Vec<f32> = vec![0.0; self.vals.len()];
+        let len = (y1 - y0 + 1) as usize;
+        let mut column: Vec<f32> = vec![0.0; self.height];
+        let mut out: Vec<f32> = vec![0.0; self.height];
+        for ix in 0..self.width {
+            for (jx, val) in column.iter_mut().enumerate() {
+                *val = self.vals[jx * self.width + ix];
+            }
+            sliding(&column, y0, len, op, &mut out);
+            for (jx, val) in out.iter().enumerate() {
+                vals[jx * self.width + ix] = *val;
+            }
+        }
+        Plane { width: self.width, height: self.height, vals }
+    }
+
+    fn runs(&self, runs: &[(i32, i32, i32)], op: MorphOp) -> Plane {
+        // Filter each distinct run width once, then combine rows.
+        let mut rows: HashMap<(i32, i32), Plane> = HashMap::new();
+        for (_, x0, x1) in runs {
+            rows.entry((*x0, *x1)).or_insert_with(|| self.horizontal(*x0, *x1, op));
+        }
+        let mut vals: Vec<f32> = vec![op.identity(); self.vals.len()];
+        for (dy, x0, x1) in runs {
+            let filtered = &rows[&(*x0, *x1)];
+            for jx in 0..self.height as i32 {
+                let srcy = jx + dy;
+                if srcy < 0 || srcy >= self.height as i32 {
+                    continue;
+                }
+                let dest = jx as usize * self.width;
+                let src = srcy as usize * self.width;
+                for ix in 0..self.width {
+                    vals[dest + ix] = op.apply(vals[dest + ix], filtered.vals[src + ix]);
+                }
+            }
+        }
+        Plane { width: self.width, height: self.height, vals }
+    }
+
+    fn filter(&self, elem: &StructElem, op: MorphOp) -> Plane {
+        match elem {
+            StructElem::Square(rad) => self.horizontal(-rad, *rad, op).vertical(-rad, *rad, op),
+            StructElem::Cross(rad) => {
+                let horiz = self.horizontal(-rad, *rad, op);
+                let vert = self.vertical(-rad, *rad, op);
+                let vals = horiz.vals.iter().zip(&vert.vals).map(|(v1, v2)| op.apply(*v1, *v2)).collect();
+                Plane { width: self.width, height: self.height, vals }
+            },
+            StructElem::Disc(rad) => self.runs(&StructElem::disc_runs(*rad), op),
+            StructElem::Runs(runs) => self.runs(runs, op),
+        }
+    }
+}
+
+impl Img<f32> {
+
+    pub fn morph(&self, elem: &StructElem, op: MorphOp) -> Img<f32> {
+        let plane = |func: fn(&Pix<f32>) -> f32| Plane {
+            width: self.width,
+            height: self.height,
+            vals: self.pixels.iter().map(func).collect(),
+        };
+        let rplane = plane(|pix| pix.r).filter(elem, op);
+        let gplane = plane(|pix| pix.g).filter(elem, op);
+        let bplane = plane(|pix| pix.b).filter(elem, op);
+        // An element that misses the centre can hang off the image
+        // entirely; such pixels keep their value.
+        let pick = |val: f32, orig: f32| if val.is_finite() { val } else { orig };
+        let mut res = Img::new(self.width, self.height);
+        for (index, pix) in res.pixels.iter_mut().enumerate() {
+            let orig = &self.pixels[index];
+            *pix = Pix::new(pick(rplane.vals[index], orig.r), pick(gplane.vals[index], orig.g), pick(bplane.vals[index], orig.b));
+        }
+        res
+    }
+
+    pub fn erode(&self, elem: &StructElem) -> Img<f32> {
+        self.morph(elem, MorphOp::Erode)
+    }
+
+    pub fn dilate(&self, elem: &StructElem) -> Img<f32> {
+        self.morph(&elem.reflected(), MorphOp::Dilate)
+    }
+
+    pub fn morph_open(&self, elem: &StructElem) -> Img<f32> {
+        self.erode(elem).dilate(elem)
+    }
+
+    pub fn morph_close(&self, elem: &StructElem) -> Img<f32> {
+        self.dilate(elem).erode(elem)
+    }
+
+    pub fn tophat(&self, elem: &StructElem) -> Img<f32> {
+        // White top-hat: what opening removes.
+        let opened = self.morph_open(elem);
+        self.combine(&opened, |p1, p2| Pix::new(p1.r - p2.r, p1.g - p2.g, p1.b - p2.b))
+    }
+
+    pub fn morph_gradient(&self, elem: &StructElem) -> Img<f32> {
+        let dilated = self.dilate(elem);
+        let eroded = self.erode(elem);
+        dilated.combine(&eroded, |p1, p2| Pix::new(p1.r - p2.r, p1.g - p2.g, p1.b - p2.b))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row_img(vals: &[f32]) -> Img<f32> {
+        let mut img = Img::new(vals.len(), 1);
+        for (ix, val) in vals.iter().enumerate() {
+            img.set(ix, 0, Pix::grey(*val));
+        }
+        img
+    }
+
+    fn reds(img: &Img<f32>) -> Vec<f32> {
+        img.pixels.iter().map(|pix| pix.r).collect()
+    }
+
+    #[test]
+    fn asymmetric_open_close() {
+        // A two-pixel element whose centre is its right-hand pixel. A
+        // run of exactly that shape survives opening and closing in
+        // place.
+        let elem = StructElem::from_img(&row_img(&[255.0, 255.0]));
+        let vals = [0.0, 0.0, 0.0, 255.0, 255.0, 0.0, 0.0];
+        let img = row_img(&vals);
+        assert_eq!(reds(&img.erode(&elem)), [0.0, 0.0, 0.0, 0.0, 255.0, 0.0, 0.0]);
+        assert_eq!(reds(&img.dilate(&elem)), [0.0, 0.0, 255.0, 255.0, 255.0, 0.0, 0.0]);
+        assert_eq!(reds(&img.morph_open(&elem)), vals);
+        assert_eq!(reds(&img.morph_close(&elem)), vals);
+    }
+
+    #[test]
+    fn open_close_bounds() {
+        // With an L-shaped element: opening never brightens, closing
+        // never darkens, and opening twice changes nothing.
+        let mut elemimg = Img::new(3, 3);
+        for (ix, jx) in [(1, 1), (2, 1), (1, 2)] {
+            elemimg.set(ix, jx, Pix::grey(255.0));
+        }
+        let elem = StructElem::from_img(&elemimg);
+        let mut img = Img::new(9, 7);
+        for (index, pix) in img.pixels.iter_mut().enumerate() {
+            *pix = Pix::grey(((index * 73) % 256) as f32);
+        }
+        let opened = img.morph_open(&elem);
+        let closed = img.morph_close(&elem);
+        for ((orig, op), cl) in img.pixels.iter().zip(&opened.pixels).zip(&closed.pixels) {
+            assert!(op.r <= orig.r && orig.r <= cl.r);
+        }
+        assert_eq!(reds(&opened.morph_open(&elem)), reds(&opened));
+    }
+}
@@ -0,0 +1,271 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+use crate::img::random::hash_u64;
+use crate::img::random::hash_unit;
+
+// Gradient and cellular noise. All of these are pure functions of the
+// seed and the sample point, so the same field can be sampled one point
+// at a time or as a whole image.
+//
+// Perlin and simplex values are roughly in [-1, 1]; Worley values are
+// the distance to the nearest feature point, in cell units (mostly in
+// [0, 1]). A period (in cells) makes the noise tile.
+
+#[derive(Debug, Clone, Copy)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    Worley,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fractal {
+    pub octaves: i32,
+    pub lacunarity: f32,
+    pub gain: f32,
+    pub turbulence: bool,
+}
+
+fn wrap(val: i32, period: Option<i32>) -> u64 {
+    match period {
+        Some(per) => val.rem_euclid(per) as u64,
+        None => val as i64 as u64,
+    }
+}
+
+fn gradient(seed: u64, ix: u64, iy: u64) -> (f32, f32) {
+    let angle = std::f32::consts::TAU * hash_unit(seed, ix, iy, 0);
+    (angle.cos(), angle.sin())
+}
+
+fn fade(val: f32) -> f32 {
+    val * val * val * (val * (val * 6.0 - 15.0) + 10.0)
+}
+
+pub fn perlin(seed: u64, xpos: f32, ypos: f32, period: Option<(i32, i32)>) -> f32 {
+    let x0 = xpos.floor();
+    let y0 = ypos.floor();
+    let xf = xpos - x0;
+    let yf = ypos - y0;
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let (perx, pery) = match period {
+        Some((px, py)) => (Some(px), Some(py)),
+        None => (None, None),
+    };
+
+    let corner = |dx: i32, dy: i32| {
+        let (gx, gy) = gradient(seed, wrap(ix+dx, perx), wrap(iy+dy, pery));
+        gx * (xf - dx as f32) + gy * (yf - dy as f32)
+    };
+
+    let u = fade(xf);
+    let v = fade(yf);
+    let top = corner(0, 0) + u * (corner(1, 0) - corner(0, 0));
+    let bottom = corner(0, 1) + u * (corner(1, 1) - corner(0, 1));
+    // With unit gradients the raw range is +-sqrt(1/2).
+    std::f32::consts::SQRT_2 * (top + v * (bottom - top))
+}
+
+fn simplex_raw(seed: u64, xpos: f32, ypos: f32) -> f32 {
+    let f2: f32 = 0.5 * (3.0_f32.sqrt() - 1.0);
+    let g2: f32 = (3.0 - 3.0_f32.sqrt()) / 6.0;
+
+    let skew = (xpos + ypos) * f2;
+    let i0 = (xpos + skew).floor();
+    let j0 = (ypos + skew).floor();
+    let unskew = (i0 + j0) * g2;
+    let x0 = xpos - (i0 - unskew);
+    let y0 = ypos - (j0 - unskew);
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+
+    let corners = [
+        (0, 0, x0, y0),
+        (i1, j1, x0 - i1 as f32 + g2, y0 - j1 as f32 + g2),
+        (1, 1, x0 - 1.0 + 2.0 * g2, y0 - 1.0 + 2.0 * g2),
+    ];
+
+    let mut total: f32 = 0.0;
+    for (di, dj, xd, yd) in corners {
+        let falloff = 0.5 - xd * xd - yd * yd;
+        if falloff > 0.0 {
+            let (gx, gy) = gradient(seed, wrap(i0 as i32 + di, None), wrap(j0 as i32 + dj, None));
+            total += falloff.powi(4) * (gx * xd + gy * yd);
+        }
+    }
+    70.0 * total
+}
+
+pub fn simplex(seed: u64, xpos: f32, ypos: f32, period: Option<(i32, i32)>) -> f32 {
+    match period {
+        None => simplex_raw(seed, xpos, ypos),
+        Some((perx, pery)) => {
+            // The simplex grid is skewed, so it can't simply wrap.
+            // Instead blend four offset copies so that opposite edges
+            // match.
+            let (pw, ph) = (perx as f32, pery as f32);
+            let xf = xpos.rem_euclid(pw);
+            let yf = ypos.rem_euclid(ph);
+            let n00 = simplex_raw(seed, xf, yf);
+            let n10 = simplex_raw(seed, xf - pw, yf);
+            let n01 = simplex_raw(seed, xf, yf - ph);
+            let n11 = simplex_raw(seed, xf - pw, yf - ph);
+            let u = xf / pw;
+            let v = yf / ph;
+            let val = (1.0-u) * (1.0-v) * n00 + u * (1.0-v) * n10 + (1.0-u) * v * n01 + u * v * n11;
+            // Blending lowers the variance; scale it back up.
+            let norm = ((1.0-u)*(1.0-u) + u*u) * ((1.0-v)*(1.0-v) + v*v);
+            val / norm.sqrt()
+        },
+    }
+}
+
+pub fn worley(seed: u64, xpos: f32, ypos: f32, period: Option<(i32, i32)>) -> f32 {
+    let (ix, iy) = (xpos.floor() as i32, ypos.floor() as i32);
+    let (perx, pery) = match period {
+        Some((px, py)) => (Some(px), Some(py)),
+        None => (None, None),
+    };
+
+    let mut best = f32::MAX;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let (cx, cy) = (ix+dx, iy+dy);
+            let (hx, hy) = (wrap(cx, perx), wrap(cy, pery));
+            let fx = cx as f32 + hash_unit(seed, hx, hy, 1);
+            let fy = cy as f32 + hash_unit(seed, hx, hy, 2);
+            let dist = (fx - xpos).hypot(fy - ypos);
+            if dist < best {
+                best = dist;
+            }
+        }
+    }
+    best
+}
+
+pub fn noise(kind: NoiseKind, seed: u64, xpos: f32, ypos: f32, period: Option<(i32, i32)>) -> f32 {
+    match kind {
+        NoiseKind::Perlin => perlin(seed, xpos, ypos, period),
+        NoiseKind::Simplex => simplex(seed, xpos, ypos, period),
+        NoiseKind::Worley => worley(seed, xpos, ypos, period),
+    }
+}
+
+pub fn fractal(kind: NoiseKind, seed: u64, xpos: f32, ypos: f32, period: Option<(i32, i32)>, frac: &Fractal) -> f32 {
+    // Sum of octaves, normalized by the total amplitude. For turbulence
+    // the absolute value of each octave is summed.
+    let mut total: f32 = 0.0;
+    let mut amptotal: f32 = 0.0;
+    let mut amp: f32 = 1.0;
+    let mut freq: f32 = 1.0;
+    for octave in 0..frac.octaves {
+        let oseed = hash_u64(seed, octave as u64, 0, 0);
+        let val = match period {
+            None => noise(kind, oseed, xpos * freq, ypos * freq, None),
+            Some((perx, pery)) => {
+                // Round each octave's period to whole cells, and scale
+                // the sample point to match, so every octave tiles.
+                let opx = ((perx as f32 * freq).round() as i32).max(1);
+                let opy = ((pery as f32 * freq).round() as i32).max(1);
+                let sx = xpos * opx as f32 / perx as f32;
+                let sy = ypos * opy as f32 / pery as f32;
+                noise(kind, oseed, sx, sy, Some((opx, opy)))
+            },
+        };
+        total += amp * if frac.turbulence { val.abs() } else { val };
+        amptotal += amp;
+        amp *= frac.gain;
+        freq *= frac.lacunarity;
+    }
+    if amptotal > 0.0 { total / amptotal } else { 0.0 }
+}
+
+impl Img<f32> {
+
+    pub fn new_noise<F>(width: usize, height: usize, cells: f32, tile: bool, func: F) -> Img<f32>
+    where F: Fn(f32, f32, Option<(i32, i32)>) -> f32 {
+        // CELLS is the number of noise cells across the width; cells
+        // are square. When tiling, the cell counts are rounded so the
+        // noise wraps exactly at the image edges.
+        let mut cellsx = cells;
+        let mut cellsy = cells * height as f32 / width as f32;
+        let mut period = None;
+        if tile {
+            let perx = (cellsx.round() as i32).max(1);
+            let pery = (cellsy.round() as i32).max(1);
+            cellsx = perx as f32;
+            cellsy = pery as f32;
+            period = Some((perx, pery));
+        }
+        
+        let mut res = Img::new(width, height);
+        for jx in 0..height {
+            let ypos = (jx as f32) / (height as f32) * cellsy;
+            for ix in 0..width {
+                let xpos = (ix as f32) / (width as f32) * cellsx;
+                res.set(ix, jx, Pix::grey(func(xpos, ypos, period)));
+            }
+        }
+        res
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [NoiseKind; 3] = [NoiseKind::Perlin, NoiseKind::Simplex, NoiseKind::Worley];
+
+    fn samples() -> impl Iterator<Item = (f32, f32)> {
+        (0..200).map(|index| (index as f32 * 0.173 - 7.0, index as f32 * 0.291 - 11.0))
+    }
+
+    #[test]
+    fn noise_range_and_seed() {
+        for kind in KINDS {
+            let mut differs = false;
+            for (xpos, ypos) in samples() {
+                let val = noise(kind, 5, xpos, ypos, None);
+                assert_eq!(val, noise(kind, 5, xpos, ypos, None));
+                match kind {
+                    NoiseKind::Worley => assert!((0.0..1.5).contains(&val), "{:?} {}", kind, val),
+                    _ => assert!((-1.01..=1.01).contains(&val), "{:?} {}", kind, val),
+                }
+                differs |= val != noise(kind, 6, xpos, ypos, None);
+            }
+            assert!(differs, "{:?} ignores the seed", kind);
+        }
+        // Perlin noise is zero on the lattice.
+        assert_eq!(perlin(3, 4.0, -2.0, None), 0.0);
+    }
+
+    #[test]
+    fn noise_tiles() {
+        for kind in KINDS {
+            for (xpos, ypos) in samples() {
+                let val = noise(kind, 9, xpos, ypos, Some((4, 3)));
+                let moved = noise(kind, 9, xpos + 4.0, ypos - 3.0, Some((4, 3)));
+                assert!((val - moved).abs() < 1.0e-3, "{:?} {} vs {}", kind, val, moved);
+            }
+        }
+        let frac = Fractal { octaves: 4, lacunarity: 2.0, gain: 0.5, turbulence: false };
+        for (xpos, ypos) in samples() {
+            let val = fractal(NoiseKind::Perlin, 2, xpos, ypos, Some((5, 5)), &frac);
+            let moved = fractal(NoiseKind::Perlin, 2, xpos - 5.0, ypos + 10.0, Some((5, 5)), &frac);
+            assert!((val - moved).abs() < 1.0e-3);
+        }
+    }
+
+    #[test]
+    fn fractal_octaves() {
+        // One octave is plain noise (with the octave's seed); turbulence
+        // sums absolute values.
+        let one = Fractal { octaves: 1, lacunarity: 2.0, gain: 0.5, turbulence: false };
+        let turb = Fractal { octaves: 5, lacunarity: 2.0, gain: 0.5, turbulence: true };
+        for (xpos, ypos) in samples() {
+            let plain = noise(NoiseKind::Simplex, hash_u64(1, 0, 0, 0), xpos, ypos, None);
+            assert_eq!(fractal(NoiseKind::Simplex, 1, xpos, ypos, None, &one), plain);
+            assert!(fractal(NoiseKind::Simplex, 1, xpos, ypos, None, &turb) >= 0.0);
+        }
+    }
+}
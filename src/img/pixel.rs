@@ -18,3 +18,10 @@ impl<T: Copy> Pix<T> {
     }
 }
 
+
+impl Pix<f32> {
+    pub fn luma(&self) -> f32 {
+        // BT.601 weights, on the gamma-encoded values.
+        0.299 * self.r + 0.587 * self.g + 0.114 * self.b
+    }
+}
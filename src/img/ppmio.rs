@@ -7,6 +7,7 @@ use std::io::Write;
 use crate::img::pixel::Pix;
 use crate::img::Img;
 
+#[allow(clippy::manual_ok_err)]
 fn read_line(fl: &mut fs::File) -> Option<String> {
     let mut vec: Vec<u8> = Vec::with_capacity(80);
     
@@ -50,17 +51,20 @@ impl Error for PPMError {
 }
 
 impl From<std::io::Error> for PPMError {
+    #[allow(clippy::needless_return)]
     fn from(err: std::io::Error) -> PPMError {
         return PPMError::new(&err.to_string());
     }
 }
 
 impl From<std::num::ParseIntError> for PPMError {
+    #[allow(clippy::needless_return)]
     fn from(err: std::num::ParseIntError) -> PPMError {
         return PPMError::new(&err.to_string());
     }
 }
 
+#[allow(clippy::needless_borrows_for_generic_args, clippy::identity_op)]
 pub fn img_read(filename: &str) -> Result<Img<u8>, PPMError> {
     let mut fl = fs::File::open(&filename)?;
 
@@ -108,6 +112,7 @@ pub fn img_read(filename: &str) -> Result<Img<u8>, PPMError> {
     Ok(img)
 }
 
+#[allow(clippy::needless_borrows_for_generic_args, clippy::identity_op)]
 pub fn img_write(filename: &str, img: Img<u8>) -> Result<(), PPMError> {
     let mut fl = fs::File::create(&filename)?;
 
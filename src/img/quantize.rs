@@ -0,0 +1,324 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Reducing an image to a few colors: either N evenly spaced levels per
+// channel, or the nearest entry of a palette. Optionally dithered.
+
+// Lloyd iterations for kmeans.
+const KMEANS_ITERATIONS: usize = 12;
+
+const BAYER8: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+#[derive(Debug, Clone, Copy)]
+pub enum DitherMethod {
+    Floyd,
+    Atkinson,
+    Bayer,
+}
+
+impl DitherMethod {
+    pub fn from_name(name: &str) -> Option<DitherMethod> {
+        match name {
+            "floyd" => Some(DitherMethod::Floyd),
+            "atkinson" => Some(DitherMethod::Atkinson),
+            "bayer" => Some(DitherMethod::Bayer),
+            _ => None,
+        }
+    }
+
+    fn spread(&self) -> &'static [(i32, i32, f32)] {
+        // Error diffusion weights as (dx, dy, fraction).
+        match self {
+            DitherMethod::Floyd => &[(1, 0, 7.0/16.0), (-1, 1, 3.0/16.0), (0, 1, 5.0/16.0), (1, 1, 1.0/16.0)],
+            DitherMethod::Atkinson => &[(1, 0, 0.125), (2, 0, 0.125), (-1, 1, 0.125), (0, 1, 0.125), (1, 1, 0.125), (0, 2, 0.125)],
+            DitherMethod::Bayer => &[],
+        }
+    }
+}
+
+pub enum Quantizer {
+    Levels(usize),
+    Palette(Vec<Pix<f32>>),
+}
+
+fn distsq(p1: &Pix<f32>, p2: &Pix<f32>) -> f32 {
+    let (dr, dg, db) = (p1.r - p2.r, p1.g - p2.g, p1.b - p2.b);
+    dr * dr + dg * dg + db * db
+}
+
+impl Quantizer {
+    fn nearest(&self, pix: &Pix<f32>) -> Pix<f32> {
+        match self {
+            Quantizer::Levels(levels) => {
+                let step = 255.0 / (*levels - 1) as f32;
+                let snap = |val: f32| ((val / step).round() * step).clamp(0.0, 255.0);
+                Pix::new(snap(pix.r), snap(pix.g), snap(pix.b))
+            },
+            Quantizer::Palette(colors) => {
+                colors.iter()
+                    .min_by(|c1, c2| distsq(c1, pix).total_cmp(&distsq(c2, pix)))
+                    .cloned()
+                    .unwrap_or_else(|| pix.clone())
+            },
+        }
+    }
+
+    fn step(&self) -> f32 {
+        // Roughly the distance between neighbouring output colors; this
+        // is how far ordered dithering nudges each pixel.
+        match self {
+            Quantizer::Levels(levels) => 255.0 / (*levels - 1) as f32,
+            Quantizer::Palette(colors) => {
+                if colors.len() < 2 {
+                    return 0.0;
+                }
+                let total: f32 = colors.iter().enumerate().map(|(index, col)| {
+                    colors.iter().enumerate()
+                        .filter(|(other, _)| *other != index)
+                        .map(|(_, col2)| distsq(col, col2))
+                        .fold(f32::MAX, f32::min)
+                        .sqrt()
+                }).sum();
+                total / colors.len() as f32 / 3.0f32.sqrt()
+            },
+        }
+    }
+}
+
+fn box_mean(pixels: &[Pix<f32>]) -> Pix<f32> {
+    let mut total: Pix<f32> = Pix::default();
+    for pix in pixels {
+        total.r += pix.r;
+        total.g += pix.g;
+        total.b += pix.b;
+    }
+    let count = pixels.len().max(1) as f32;
+    Pix::new(total.r / count, total.g / count, total.b / count)
+}
+
+fn channel(pix: &Pix<f32>, chan: usize) -> f32 {
+    match chan {
+        0 => pix.r,
+        1 => pix.g,
+        _ => pix.b,
+    }
+}
+
+impl Img<f32> {
+
+    pub fn quantize(&self, quant: &Quantizer) -> Img<f32> {
+        self.map(|pix| quant.nearest(pix))
+    }
+
+    pub fn dither(&self, quant: &Quantizer, method: DitherMethod) -> Img<f32> {
+        let mut res = Img::new(self.width, self.height);
+        if let DitherMethod::Bayer = method {
+            let step = quant.step();
+            for jx in 0..self.height {
+                for ix in 0..self.width {
+                    let nudge = ((BAYER8[jx % 8][ix % 8] as f32 + 0.5) / 64.0 - 0.5) * step;
+                    let pix = self.at(ix, jx);
+                    let moved = Pix::new(pix.r + nudge, pix.g + nudge, pix.b + nudge);
+                    res.set(ix, jx, quant.nearest(&moved));
+                }
+            }
+            return res;
+        }
+
+        // Error diffusion, in plain raster order.
+        let mut work: Vec<Pix<f32>> = self.pixels.clone();
+        for jx in 0..self.height {
+            for ix in 0..self.width {
+                let index = jx * self.width + ix;
+                let old = work[index].clone();
+                let new = quant.nearest(&old);
+                let err = Pix::new(old.r - new.r, old.g - new.g, old.b - new.b);
+                for (dx, dy, frac) in method.spread() {
+                    let (nx, ny) = (ix as i32 + dx, jx as i32 + dy);
+                    if nx < 0 || nx >= self.width as i32 || ny >= self.height as i32 {
+                        continue;
+                    }
+                    let target = &mut work[ny as usize * self.width + nx as usize];
+                    target.r += err.r * frac;
+                    target.g += err.g * frac;
+                    target.b += err.b * frac;
+                }
+                res.set(ix, jx, new);
+            }
+        }
+        res
+    }
+
+    pub fn median_cut(&self, count: usize) -> Vec<Pix<f32>> {
+        // Repeatedly split the box with the widest channel range at its
+        // median, until there are COUNT boxes (or nothing left to split).
+        // Each box contributes its mean color.
+        let mut boxes: Vec<Vec<Pix<f32>>> = vec![self.pixels.clone()];
+        while boxes.len() < count {
+            let mut widest: Option<(usize, usize, f32)> = None;
+            for (index, pixels) in boxes.iter().enumerate() {
+                if pixels.len() < 2 {
+                    continue;
+                }
+                for chan in 0..3 {
+                    let lo = pixels.iter().map(|pix| channel(pix, chan)).fold(f32::MAX, f32::min);
+                    let hi = pixels.iter().map(|pix| channel(pix, chan)).fold(f32::MIN, f32::max);
+                    if hi - lo > 0.0 && widest.is_none_or(|(_, _, range)| hi - lo > range) {
+                        widest = Some((index, chan, hi - lo));
+                    }
+                }
+            }
+            let Some((index, chan, _)) = widest else {
+                break;
+            };
+            let mut pixels = boxes.swap_remove(index);
+            pixels.sort_by(|p1, p2| channel(p1, chan).total_cmp(&channel(p2, chan)));
+            let upper = pixels.split_off(pixels.len() / 2);
+            boxes.push(pixels);
+            boxes.push(upper);
+        }
+        let mut res: Vec<Pix<f32>> = boxes.iter().map(|pixels| box_mean(pixels)).collect();
+        res.sort_by(|p1, p2| p1.luma().total_cmp(&p2.luma()));
+        res
+    }
+
+    pub fn kmeans(&self, count: usize) -> Vec<Pix<f32>> {
+        // Lloyd's algorithm, starting from the median-cut palette so
+        // that the result is repeatable.
+        let mut centers = self.median_cut(count);
+        for _ in 0..KMEANS_ITERATIONS {
+            let mut sums: Vec<(Pix<f32>, usize)> = vec![(Pix::default(), 0); centers.len()];
+            for pix in &self.pixels {
+                let nearest = (0..centers.len())
+                    .min_by(|i1, i2| distsq(&centers[*i1], pix).total_cmp(&distsq(&centers[*i2], pix)))
+                    .unwrap_or(0);
+                let (total, num) = &mut sums[nearest];
+                total.r += pix.r;
+                total.g += pix.g;
+                total.b += pix.b;
+                *num += 1;
+            }
+            let mut moved = false;
+            for (center, (total, num)) in centers.iter_mut().zip(sums) {
+                if num == 0 {
+                    continue;
+                }
+                let mean = Pix::new(total.r / num as f32, total.g / num as f32, total.b / num as f32);
+                if distsq(center, &mean) > 1.0e-6 {
+                    moved = true;
+                }
+                *center = mean;
+            }
+            if !moved {
+                break;
+            }
+        }
+        centers.sort_by(|p1, p2| p1.luma().total_cmp(&p2.luma()));
+        centers
+    }
+
+    pub fn wrap_bytes(&self) -> Img<f32> {
+        // Round-trip through as_u8_wrap: each channel is truncated to an
+        // integer and taken mod 256.
+        self.as_u8_wrap().as_f32()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [DitherMethod; 3] = [DitherMethod::Floyd, DitherMethod::Atkinson, DitherMethod::Bayer];
+
+    fn ramp() -> Img<f32> {
+        let mut img = Img::new(32, 16);
+        for jx in 0..16 {
+            for ix in 0..32 {
+                img.set(ix, jx, Pix::grey(ix as f32 * 255.0 / 31.0));
+            }
+        }
+        img
+    }
+
+    fn tones(img: &Img<f32>) -> Vec<f32> {
+        let mut res: Vec<f32> = img.pixels.iter().flat_map(|pix| [pix.r, pix.g, pix.b]).collect();
+        res.sort_by(|v1, v2| v1.total_cmp(v2));
+        res.dedup();
+        res
+    }
+
+    #[test]
+    fn level_count() {
+        let quant = Quantizer::Levels(4);
+        assert_eq!(tones(&ramp().quantize(&quant)), vec![0.0, 85.0, 170.0, 255.0]);
+        for method in METHODS {
+            let res = tones(&ramp().dither(&quant, method));
+            assert!(res.iter().all(|val| [0.0, 85.0, 170.0, 255.0].contains(val)), "{:?} {:?}", method, res);
+        }
+    }
+
+    #[test]
+    fn dither_keeps_average() {
+        // A flat 100 grey dithered to black and white comes out about
+        // 100/255 white.
+        let img = Img::new_constant(32, 32, Pix::grey(100.0));
+        for method in METHODS {
+            let res = img.dither(&Quantizer::Levels(2), method);
+            assert_eq!(tones(&res), vec![0.0, 255.0], "{:?}", method);
+            assert!((res.average().r - 100.0).abs() < 8.0, "{:?} {}", method, res.average().r);
+        }
+    }
+
+    #[test]
+    fn palette_quantize() {
+        let palette = vec![Pix::new(255.0, 0.0, 0.0), Pix::new(0.0, 0.0, 255.0), Pix::grey(255.0)];
+        let quant = Quantizer::Palette(palette);
+        let img = ramp().map(|pix| Pix::new(pix.r, 40.0, 255.0 - pix.r));
+        for method in METHODS {
+            let res = img.dither(&quant, method);
+            assert!(res.pixels.iter().all(|pix| pix.g == 0.0 || pix.g == 255.0), "{:?}", method);
+        }
+        let res = img.quantize(&quant);
+        assert_eq!((res.at(0, 0).r, res.at(0, 0).b), (0.0, 255.0));
+        assert_eq!((res.at(31, 0).r, res.at(31, 0).b), (255.0, 0.0));
+    }
+
+    #[test]
+    fn palette_extraction() {
+        let mut img = Img::new(6, 2);
+        let colors = [Pix::new(10.0, 20.0, 30.0), Pix::new(200.0, 40.0, 40.0), Pix::new(240.0, 240.0, 240.0)];
+        for (index, pix) in img.pixels.iter_mut().enumerate() {
+            *pix = colors[index % 3].clone();
+        }
+        // Median cut only approximates three clusters; kmeans settles on
+        // them exactly. Both come out darkest first.
+        assert_eq!(img.median_cut(3).len(), 3);
+        let got: Vec<(f32, f32, f32)> = img.kmeans(3).iter().map(|pix| (pix.r, pix.g, pix.b)).collect();
+        assert_eq!(got, vec![(10.0, 20.0, 30.0), (200.0, 40.0, 40.0), (240.0, 240.0, 240.0)]);
+
+        // Two colours split evenly, and can't make more than two boxes.
+        let mut pair = Img::new(4, 2);
+        for (index, pix) in pair.pixels.iter_mut().enumerate() {
+            *pix = Pix::grey(if index % 2 == 0 { 10.0 } else { 100.0 });
+        }
+        let got: Vec<f32> = pair.median_cut(8).iter().map(|pix| pix.r).collect();
+        assert_eq!(got, vec![10.0, 100.0]);
+    }
+
+    #[test]
+    fn wrap_bytes_wraps() {
+        let mut img = Img::new(3, 1);
+        img.pixels = vec![Pix::grey(256.0), Pix::grey(300.7), Pix::grey(12.0)];
+        assert_eq!(tones(&img.wrap_bytes()), vec![0.0, 12.0, 44.0]);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Random images are generated by hashing each pixel's coordinates with
+// a seed, rather than by drawing from a sequential RNG. That way the
+// result doesn't depend on the order in which pixels are computed.
+
+pub fn hash_u64(seed: u64, xpos: u64, ypos: u64, chan: u64) -> u64 {
+    // splitmix64 finalizer, applied to each input in turn.
+    fn mix(val: u64) -> u64 {
+        let mut z = val.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+    mix(mix(mix(mix(seed) ^ xpos) ^ ypos) ^ chan)
+}
+
+pub fn hash_unit(seed: u64, xpos: u64, ypos: u64, chan: u64) -> f32 {
+    // Uniform in [0, 1).
+    (hash_u64(seed, xpos, ypos, chan) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+pub fn hash_gauss(seed: u64, xpos: u64, ypos: u64, chan: u64) -> f32 {
+    // Standard normal, by the Box-Muller transform.
+    let u1 = 1.0 - hash_unit(seed, xpos, ypos, 2*chan);
+    let u2 = hash_unit(seed, xpos, ypos, 2*chan+1);
+    (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+}
+
+impl Img<f32> {
+
+    pub fn random_uniform(width: usize, height: usize, seed: u64, grey: bool) -> Img<f32> {
+        let mut res = Img::new(width, height);
+        for jx in 0..height {
+            for ix in 0..width {
+                let (xp, yp) = (ix as u64, jx as u64);
+                let pix = if grey {
+                    Pix::grey(255.0 * hash_unit(seed, xp, yp, 0))
+                }
+                else {
+                    Pix::new(255.0 * hash_unit(seed, xp, yp, 0), 255.0 * hash_unit(seed, xp, yp, 1), 255.0 * hash_unit(seed, xp, yp, 2))
+                };
+                res.set(ix, jx, pix);
+            }
+        }
+        res
+    }
+
+    pub fn random_gauss(width: usize, height: usize, seed: u64, mean: f32, stddev: f32, grey: bool) -> Img<f32> {
+        let mut res = Img::new(width, height);
+        for jx in 0..height {
+            for ix in 0..width {
+                let (xp, yp) = (ix as u64, jx as u64);
+                let pix = if grey {
+                    Pix::grey(mean + stddev * hash_gauss(seed, xp, yp, 0))
+                }
+                else {
+                    Pix::new(mean + stddev * hash_gauss(seed, xp, yp, 0), mean + stddev * hash_gauss(seed, xp, yp, 1), mean + stddev * hash_gauss(seed, xp, yp, 2))
+                };
+                res.set(ix, jx, pix);
+            }
+        }
+        res
+    }
+
+}
@@ -0,0 +1,408 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Per-channel statistics, and the tone adjustments built on them.
+// Channels are treated independently throughout, on the 0..255 scale.
+
+// Histogram bins used by equalize and clahe.
+const TONES: usize = 256;
+
+fn tone_index(val: f32) -> usize {
+    (val.round().clamp(0.0, 255.0)) as usize
+}
+
+fn percentile_sorted(sorted: &[f32], pct: f32) -> f32 {
+    // Linear interpolation between the closest ranks.
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let pos = (pct / 100.0).clamp(0.0, 1.0) * (sorted.len() - 1) as f32;
+    let lo = pos.floor() as usize;
+    let hi = (lo + 1).min(sorted.len() - 1);
+    let frac = pos - lo as f32;
+    sorted[lo] + (sorted[hi] - sorted[lo]) * frac
+}
+
+fn stretch(val: f32, lo: f32, hi: f32) -> f32 {
+    // Map lo..hi to 0..255. A flat channel is left alone.
+    if hi - lo <= f32::EPSILON {
+        val
+    }
+    else {
+        ((val - lo) * 255.0 / (hi - lo)).clamp(0.0, 255.0)
+    }
+}
+
+fn cdf_table(hist: &[u32; TONES]) -> [f32; TONES] {
+    // Map each tone to 0..255 by its cumulative count, with the first
+    // occupied tone going to zero.
+    let mut table = [0.0; TONES];
+    let total: u32 = hist.iter().sum();
+    let first = hist.iter().find(|count| **count > 0).copied().unwrap_or(0);
+    if total <= first {
+        for (index, val) in table.iter_mut().enumerate() {
+            *val = index as f32;
+        }
+        return table;
+    }
+    let mut cumul: u32 = 0;
+    for (index, count) in hist.iter().enumerate() {
+        cumul += count;
+        table[index] = (cumul.saturating_sub(first)) as f32 * 255.0 / (total - first) as f32;
+    }
+    table
+}
+
+impl Img<f32> {
+
+    fn channels(&self) -> [Vec<f32>; 3] {
+        [
+            self.pixels.iter().map(|pix| pix.r).collect(),
+            self.pixels.iter().map(|pix| pix.g).collect(),
+            self.pixels.iter().map(|pix| pix.b).collect(),
+        ]
+    }
+
+    fn sorted_channels(&self) -> [Vec<f32>; 3] {
+        let mut chans = self.channels();
+        for chan in chans.iter_mut() {
+            chan.sort_by(|v1, v2| v1.total_cmp(v2));
+        }
+        chans
+    }
+
+    pub fn histogram(&self, bins: usize) -> [Vec<u32>; 3] {
+        // BINS equal bins spanning 0..256. Out-of-range values land in
+        // the end bins.
+        let mut res = [vec![0; bins], vec![0; bins], vec![0; bins]];
+        for (hist, chan) in res.iter_mut().zip(self.channels()) {
+            for val in chan {
+                let index = ((val / 256.0 * bins as f32).floor().max(0.0) as usize).min(bins - 1);
+                hist[index] += 1;
+            }
+        }
+        res
+    }
+
+    pub fn minmax(&self) -> (Pix<f32>, Pix<f32>) {
+        let mut lo = Pix::grey(f32::MAX);
+        let mut hi = Pix::grey(f32::MIN);
+        for pix in &self.pixels {
+            lo = Pix::new(lo.r.min(pix.r), lo.g.min(pix.g), lo.b.min(pix.b));
+            hi = Pix::new(hi.r.max(pix.r), hi.g.max(pix.g), hi.b.max(pix.b));
+        }
+        (lo, hi)
+    }
+
+    pub fn stddev(&self) -> Pix<f32> {
+        let avpix = self.average();
+        let mut total: Pix<f32> = Pix::default();
+        for pix in &self.pixels {
+            total.r += (pix.r - avpix.r) * (pix.r - avpix.r);
+            total.g += (pix.g - avpix.g) * (pix.g - avpix.g);
+            total.b += (pix.b - avpix.b) * (pix.b - avpix.b);
+        }
+        let pixcount: f32 = self.pixcount() as f32;
+        Pix::new((total.r / pixcount).sqrt(), (total.g / pixcount).sqrt(), (total.b / pixcount).sqrt())
+    }
+
+    pub fn percentile(&self, pct: f32) -> Pix<f32> {
+        let [rchan, gchan, bchan] = self.sorted_channels();
+        Pix::new(percentile_sorted(&rchan, pct), percentile_sorted(&gchan, pct), percentile_sorted(&bchan, pct))
+    }
+
+    pub fn normalize(&self) -> Img<f32> {
+        // Stretch each channel so that it spans 0..255.
+        let (lo, hi) = self.minmax();
+        self.map(|pix| Pix {
+            r: stretch(pix.r, lo.r, hi.r),
+            g: stretch(pix.g, lo.g, hi.g),
+            b: stretch(pix.b, lo.b, hi.b),
+        })
+    }
+
+    pub fn autolevels(&self, pct: f32) -> Img<f32> {
+        // Like normalize, but the darkest and brightest PCT percent of
+        // each channel are clipped rather than counted.
+        let [rchan, gchan, bchan] = self.sorted_channels();
+        let lo = Pix::new(percentile_sorted(&rchan, pct), percentile_sorted(&gchan, pct), percentile_sorted(&bchan, pct));
+        let hi = Pix::new(percentile_sorted(&rchan, 100.0-pct), percentile_sorted(&gchan, 100.0-pct), percentile_sorted(&bchan, 100.0-pct));
+        self.map(|pix| Pix {
+            r: stretch(pix.r, lo.r, hi.r),
+            g: stretch(pix.g, lo.g, hi.g),
+            b: stretch(pix.b, lo.b, hi.b),
+        })
+    }
+
+    fn tone_histograms(&self, xmin: usize, ymin: usize, xmax: usize, ymax: usize) -> [[u32; TONES]; 3] {
+        let mut res = [[0; TONES]; 3];
+        for jx in ymin..ymax {
+            for ix in xmin..xmax {
+                let pix = self.at(ix, jx);
+                res[0][tone_index(pix.r)] += 1;
+                res[1][tone_index(pix.g)] += 1;
+                res[2][tone_index(pix.b)] += 1;
+            }
+        }
+        res
+    }
+
+    pub fn equalize(&self) -> Img<f32> {
+        // Global histogram equalisation.
+        let hists = self.tone_histograms(0, 0, self.width, self.height);
+        let tables = hists.map(|hist| cdf_table(&hist));
+        self.map(|pix| Pix {
+            r: tables[0][tone_index(pix.r)],
+            g: tables[1][tone_index(pix.g)],
+            b: tables[2][tone_index(pix.b)],
+        })
+    }
+
+    pub fn clahe(&self, tiles: usize, clip: f32) -> Img<f32> {
+        // Contrast-limited adaptive equalisation. The image is cut into
+        // TILESxTILES tiles, each equalised separately; histogram bins
+        // above CLIP times the average bin are cut down and the excess
+        // spread evenly. Pixels blend the tables of the four nearest
+        // tile centres.
+        let xtiles = tiles.clamp(1, self.width.max(1));
+        let ytiles = tiles.clamp(1, self.height.max(1));
+        let mut tables: Vec<[[f32; TONES]; 3]> = Vec::with_capacity(xtiles * ytiles);
+        for ty in 0..ytiles {
+            for tx in 0..xtiles {
+                let (xmin, xmax) = (tx * self.width / xtiles, (tx+1) * self.width / xtiles);
+                let (ymin, ymax) = (ty * self.height / ytiles, (ty+1) * self.height / ytiles);
+                let mut hists = self.tone_histograms(xmin, ymin, xmax, ymax);
+                let limit = ((clip * ((xmax-xmin) * (ymax-ymin)) as f32 / TONES as f32).ceil() as u32).max(1);
+                for hist in hists.iter_mut() {
+                    let mut excess: u32 = 0;
+                    for count in hist.iter_mut() {
+                        if *count > limit {
+                            excess += *count - limit;
+                            *count = limit;
+                        }
+                    }
+                    let share = excess / TONES as u32;
+                    let extra = (excess % TONES as u32) as usize;
+                    for (index, count) in hist.iter_mut().enumerate() {
+                        *count += share + if index < extra { 1 } else { 0 };
+                    }
+                }
+                tables.push(hists.map(|hist| cdf_table(&hist)));
+            }
+        }
+
+        let tilepos = |pos: usize, size: usize, count: usize| -> (usize, usize, f32) {
+            // The two tile columns (or rows) around POS, and the weight
+            // of the second.
+            let fpos = (pos as f32 + 0.5) * count as f32 / size as f32 - 0.5;
+            let fpos = fpos.clamp(0.0, (count - 1) as f32);
+            let lo = fpos.floor() as usize;
+            let hi = (lo + 1).min(count - 1);
+            (lo, hi, fpos - lo as f32)
+        };
+
+        let mut res: Img<f32> = Img::new(self.width, self.height);
+        for jx in 0..self.height {
+            let (ty0, ty1, yfrac) = tilepos(jx, self.height, ytiles);
+            for ix in 0..self.width {
+                let (tx0, tx1, xfrac) = tilepos(ix, self.width, xtiles);
+                let pix = self.at(ix, jx);
+                let mut vals = [pix.r, pix.g, pix.b];
+                for (chan, val) in vals.iter_mut().enumerate() {
+                    let tone = tone_index(*val);
+                    let v00 = tables[ty0 * xtiles + tx0][chan][tone];
+                    let v01 = tables[ty0 * xtiles + tx1][chan][tone];
+                    let v10 = tables[ty1 * xtiles + tx0][chan][tone];
+                    let v11 = tables[ty1 * xtiles + tx1][chan][tone];
+                    let top = v00 + (v01 - v00) * xfrac;
+                    let bottom = v10 + (v11 - v10) * xfrac;
+                    *val = top + (bottom - top) * yfrac;
+                }
+                res.set(ix, jx, Pix::new(vals[0], vals[1], vals[2]));
+            }
+        }
+        res
+    }
+
+    fn luma_mask<F>(&self, func: F) -> Img<f32>
+    where F: Fn(usize, f32) -> bool {
+        // A 0/1 grey mask from each pixel's index and luma.
+        let mut res = Img::new(self.width, self.height);
+        for (index, pix) in res.pixels.iter_mut().enumerate() {
+            *pix = Pix::grey(if func(index, self.pixels[index].luma()) { 1.0 } else { 0.0 });
+        }
+        res
+    }
+
+    pub fn threshold(&self, level: f32) -> Img<f32> {
+        // 1 where the luma is above LEVEL.
+        self.luma_mask(|_, luma| luma > level)
+    }
+
+    pub fn otsu_level(&self) -> f32 {
+        // The luma threshold which maximises the between-class variance
+        // of the two sides (Otsu's method).
+        let mut hist = [0u32; TONES];
+        for pix in &self.pixels {
+            hist[tone_index(pix.luma())] += 1;
+        }
+        let total = self.pixcount() as f64;
+        let sumall: f64 = hist.iter().enumerate().map(|(index, count)| index as f64 * *count as f64).sum();
+        let (mut countlo, mut sumlo) = (0.0f64, 0.0f64);
+        let (mut best, mut bestvar) = (0, -1.0f64);
+        for (index, count) in hist.iter().enumerate() {
+            countlo += *count as f64;
+            sumlo += index as f64 * *count as f64;
+            let counthi = total - countlo;
+            if countlo <= 0.0 || counthi <= 0.0 {
+                continue;
+            }
+            let meanlo = sumlo / countlo;
+            let meanhi = (sumall - sumlo) / counthi;
+            let var = countlo * counthi * (meanlo - meanhi) * (meanlo - meanhi);
+            if var > bestvar {
+                bestvar = var;
+                best = index;
+            }
+        }
+        // Tones up to and including BEST are the dark class.
+        best as f32 + 0.5
+    }
+
+    pub fn adaptive_threshold(&self, block: usize, offset: f32, gaussian: bool) -> Img<f32> {
+        // 1 where the luma is above the local mean (over a BLOCK-sized
+        // square, or a Gaussian of similar extent) minus OFFSET.
+        let lumas: Vec<f32> = self.pixels.iter().map(|pix| pix.luma()).collect();
+        let local: Vec<f32> = if gaussian {
+            let sigma = 0.3 * ((block as f32 - 1.0) * 0.5 - 1.0) + 0.8;
+            let grey = self.map(|pix| Pix::grey(pix.luma()));
+            grey.gaussblur(sigma).pixels.iter().map(|pix| pix.r).collect()
+        }
+        else {
+            // Box mean from a summed-area table, clipped at the edges.
+            let (width, height) = (self.width, self.height);
+            let mut table: Vec<f64> = vec![0.0; (width + 1) * (height + 1)];
+            for jx in 0..height {
+                let mut rowsum: f64 = 0.0;
+                for ix in 0..width {
+                    rowsum += lumas[jx * width + ix] as f64;
+                    table[(jx + 1) * (width + 1) + ix + 1] = table[jx * (width + 1) + ix + 1] + rowsum;
+                }
+            }
+            let rad = block / 2;
+            let mut means: Vec<f32> = Vec::with_capacity(lumas.len());
+            for jx in 0..height {
+                let (y0, y1) = (jx.saturating_sub(rad), (jx + rad + 1).min(height));
+                for ix in 0..width {
+                    let (x0, x1) = (ix.saturating_sub(rad), (ix + rad + 1).min(width));
+                    let sum = table[y1 * (width + 1) + x1] - table[y0 * (width + 1) + x1]
+                        - table[y1 * (width + 1) + x0] + table[y0 * (width + 1) + x0];
+                    means.push((sum / ((x1 - x0) * (y1 - y0)) as f64) as f32);
+                }
+            }
+            means
+        };
+        self.luma_mask(|index, luma| luma > local[index] - offset)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey_row(vals: &[f32]) -> Img<f32> {
+        let mut img = Img::new(vals.len(), 1);
+        for (pix, val) in img.pixels.iter_mut().zip(vals) {
+            *pix = Pix::grey(*val);
+        }
+        img
+    }
+
+    fn reds(img: &Img<f32>) -> Vec<f32> {
+        img.pixels.iter().map(|pix| pix.r).collect()
+    }
+
+    #[test]
+    fn histogram_bins() {
+        let img = grey_row(&[0.0, 63.0, 64.0, 255.0, 300.0, -5.0]);
+        let [rhist, ghist, bhist] = img.histogram(4);
+        assert_eq!(rhist, vec![3, 1, 0, 2]);
+        assert_eq!(ghist, rhist);
+        assert_eq!(bhist, rhist);
+        assert_eq!(img.histogram(1)[0], vec![6]);
+    }
+
+    #[test]
+    fn basic_statistics() {
+        let img = grey_row(&[0.0, 255.0, 0.0, 255.0]);
+        let (lo, hi) = img.minmax();
+        assert_eq!((lo.r, hi.r), (0.0, 255.0));
+        assert_eq!(img.stddev().r, 127.5);
+        let img = grey_row(&[30.0, 0.0, 20.0, 10.0]);
+        assert_eq!(img.percentile(50.0).r, 15.0);
+        assert_eq!(img.percentile(0.0).r, 0.0);
+        assert_eq!(img.percentile(100.0).r, 30.0);
+    }
+
+    #[test]
+    fn stretching() {
+        let img = grey_row(&[50.0, 100.0, 150.0]);
+        assert_eq!(reds(&img.normalize()), vec![0.0, 127.5, 255.0]);
+        // Clipping a quarter at each end stretches 100..200 instead.
+        let img = grey_row(&[0.0, 100.0, 150.0, 200.0, 255.0]);
+        assert_eq!(reds(&img.autolevels(25.0)), vec![0.0, 0.0, 127.5, 255.0, 255.0]);
+        // A flat channel is left alone.
+        assert_eq!(reds(&grey_row(&[40.0, 40.0]).normalize()), vec![40.0, 40.0]);
+    }
+
+    #[test]
+    fn equalize_spreads_tones() {
+        let img = grey_row(&[10.0, 10.0, 11.0, 12.0, 12.0, 13.0]);
+        let res = reds(&img.equalize());
+        assert_eq!(res[0], 0.0);
+        assert_eq!(res[5], 255.0);
+        assert!(res.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let mut img = Img::new(16, 16);
+        for (index, pix) in img.pixels.iter_mut().enumerate() {
+            *pix = Pix::grey(100.0 + (index % 7) as f32);
+        }
+        let res = img.clahe(4, 2.0);
+        assert!(res.pixels.iter().all(|pix| (0.0..=255.0).contains(&pix.r)));
+        let (lo, hi) = res.minmax();
+        assert!(hi.r - lo.r > 6.0, "clahe should add contrast: {} {}", lo.r, hi.r);
+    }
+
+    #[test]
+    fn threshold_masks() {
+        let img = grey_row(&[0.0, 100.0, 101.0, 255.0]);
+        assert_eq!(reds(&img.threshold(100.0)), vec![0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn otsu_bimodal() {
+        let vals: Vec<f32> = (0..20).map(|index| if index % 3 == 0 { 200.0 } else { 40.0 + (index % 2) as f32 }).collect();
+        let img = grey_row(&vals);
+        let level = img.otsu_level();
+        assert!(level > 41.0 && level < 200.0, "{}", level);
+        let mask = img.threshold(level);
+        for (val, bit) in vals.iter().zip(reds(&mask)) {
+            assert_eq!(bit, if *val == 200.0 { 1.0 } else { 0.0 });
+        }
+    }
+
+    #[test]
+    fn adaptive_threshold_spot() {
+        // A faint spot on a flat background stands out from its local
+        // mean, whatever the absolute level.
+        let mut vals = vec![50.0; 9];
+        vals[4] = 60.0;
+        let img = grey_row(&vals);
+        for gaussian in [false, true] {
+            let res = reds(&img.adaptive_threshold(3, -1.0, gaussian));
+            let want: Vec<f32> = (0..9).map(|index| if index == 4 { 1.0 } else { 0.0 }).collect();
+            assert_eq!(res, want, "gaussian {}", gaussian);
+        }
+    }
+}
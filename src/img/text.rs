@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+
+use crate::img::Img;
+use crate::img::draw::Paint;
+use crate::img::font5x7::FONT5X7;
+
+// Bitmap fonts: the built-in 5x7 font, or a BDF or PSF (v1 or v2) file
+// loaded from disk.
+
+struct Glyph {
+    width: usize,
+    height: usize,
+    xoff: i32,      // from the left of the cell
+    yoff: i32,      // from the top of the cell
+    advance: i32,
+    bits: Vec<bool>,
+}
+
+pub struct Font {
+    lineheight: i32,
+    glyphs: HashMap<char, Glyph>,
+}
+
+impl Font {
+    pub fn builtin() -> Font {
+        let mut glyphs: HashMap<char, Glyph> = HashMap::new();
+        for (index, rows) in FONT5X7.iter().enumerate() {
+            let ch = char::from(0x20 + index as u8);
+            let mut bits: Vec<bool> = Vec::with_capacity(35);
+            for row in rows {
+                for col in 0..5 {
+                    bits.push(row & (0x10 >> col) != 0);
+                }
+            }
+            glyphs.insert(ch, Glyph { width: 5, height: 7, xoff: 0, yoff: 0, advance: 6, bits });
+        }
+        Font { lineheight: 8, glyphs }
+    }
+
+    pub fn load(filename: &str) -> Result<Font, String> {
+        let data = std::fs::read(filename)
+            .map_err(|err| format!("{}: {}", filename, err))?;
+        let res = if data.starts_with(&[0x72, 0xB5, 0x4A, 0x86]) {
+            Font::parse_psf2(&data)
+        }
+        else if data.starts_with(&[0x36, 0x04]) {
+            Font::parse_psf1(&data)
+        }
+        else if data.starts_with(b"STARTFONT") {
+            Font::parse_bdf(&String::from_utf8_lossy(&data))
+        }
+        else {
+            Err("not a BDF or PSF font".to_string())
+        };
+        res.map_err(|err| format!("{}: {}", filename, err))
+    }
+
+    fn psf_glyph(data: &[u8], width: usize, height: usize) -> Glyph {
+        let rowbytes = width.div_ceil(8);
+        let mut bits: Vec<bool> = Vec::with_capacity(width * height);
+        for jx in 0..height {
+            for ix in 0..width {
+                let byte = data[jx * rowbytes + ix / 8];
+                bits.push(byte & (0x80 >> (ix % 8)) != 0);
+            }
+        }
+        Glyph { width, height, xoff: 0, yoff: 0, advance: width as i32, bits }
+    }
+
+    fn parse_psf1(data: &[u8]) -> Result<Font, String> {
+        if data.len() < 4 {
+            return Err("PSF1 header is truncated".to_string());
+        }
+        let mode = data[2];
+        let height = data[3] as usize;
+        let count = if mode & 0x01 != 0 { 512 } else { 256 };
+        let tablestart = 4 + count * height;
+        if data.len() < tablestart {
+            return Err("PSF1 file is truncated".to_string());
+        }
+        let glyphdata = |index: usize| &data[4 + index * height..4 + (index+1) * height];
+
+        let mut glyphs: HashMap<char, Glyph> = HashMap::new();
+        if mode & 0x06 != 0 {
+            // Unicode table: for each glyph, UCS-2 values ending in
+            // 0xFFFF; 0xFFFE starts a multi-char sequence, which we skip.
+            let mut pos = tablestart;
+            for index in 0..count {
+                let mut inseq = false;
+                while pos + 1 < data.len() {
+                    let val = u16::from_le_bytes([data[pos], data[pos+1]]);
+                    pos += 2;
+                    if val == 0xFFFF {
+                        break;
+                    }
+                    if val == 0xFFFE {
+                        inseq = true;
+                    }
+                    else if !inseq {
+                        if let Some(ch) = char::from_u32(val as u32) {
+                            glyphs.insert(ch, Font::psf_glyph(glyphdata(index), 8, height));
+                        }
+                    }
+                }
+            }
+        }
+        else {
+            for index in 0..count {
+                if let Some(ch) = char::from_u32(index as u32) {
+                    glyphs.insert(ch, Font::psf_glyph(glyphdata(index), 8, height));
+                }
+            }
+        }
+        Ok(Font { lineheight: height as i32, glyphs })
+    }
+
+    fn parse_psf2(data: &[u8]) -> Result<Font, String> {
+        let field = |index: usize| -> Result<usize, String> {
+            let pos = 4 * index;
+            let bytes = data.get(pos..pos+4).ok_or_else(|| "PSF2 header is truncated".to_string())?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        };
+        let headersize = field(2)?;
+        let flags = field(3)?;
+        let count = field(4)?;
+        let charsize = field(5)?;
+        let height = field(6)?;
+        let width = field(7)?;
+        if charsize < width.div_ceil(8) * height {
+            return Err("PSF2 glyph size is inconsistent".to_string());
+        }
+        let tablestart = headersize + count * charsize;
+        if data.len() < tablestart {
+            return Err("PSF2 file is truncated".to_string());
+        }
+        let glyphdata = |index: usize| &data[headersize + index * charsize..headersize + (index+1) * charsize];
+
+        let mut glyphs: HashMap<char, Glyph> = HashMap::new();
+        if flags & 0x01 != 0 {
+            // Unicode table: for each glyph, UTF-8 strings ending in
+            // 0xFF; 0xFE starts a multi-char sequence, which we skip.
+            let mut pos = tablestart;
+            for index in 0..count {
+                let end = data[pos..].iter().position(|byte| *byte == 0xFF)
+                    .map(|off| pos + off)
+                    .unwrap_or(data.len());
+                let entry = &data[pos..end];
+                let single = match entry.iter().position(|byte| *byte == 0xFE) {
+                    Some(off) => &entry[..off],
+                    None => entry,
+                };
+                for ch in String::from_utf8_lossy(single).chars() {
+                    glyphs.insert(ch, Font::psf_glyph(glyphdata(index), width, height));
+                }
+                pos = (end + 1).min(data.len());
+            }
+        }
+        else {
+            for index in 0..count {
+                if let Some(ch) = char::from_u32(index as u32) {
+                    glyphs.insert(ch, Font::psf_glyph(glyphdata(index), width, height));
+                }
+            }
+        }
+        Ok(Font { lineheight: height as i32, glyphs })
+    }
+
+    fn parse_bdf(body: &str) -> Result<Font, String> {
+        fn nums(args: &[&str]) -> Result<Vec<i32>, String> {
+            args.iter()
+                .map(|arg| arg.parse::<i32>().map_err(|_| format!("BDF bad number: {}", arg)))
+                .collect()
+        }
+
+        let mut ascent: Option<i32> = None;
+        let mut descent: i32 = 0;
+        let mut boundingbox: (i32, i32, i32, i32) = (0, 0, 0, 0);
+        let mut glyphs: HashMap<char, Glyph> = HashMap::new();
+
+        let mut encoding: i32 = -1;
+        let mut advance: i32 = 0;
+        let mut bbx: (i32, i32, i32, i32) = (0, 0, 0, 0);
+        let mut rows: Option<Vec<&str>> = None;
+
+        for line in body.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            if let Some(ls) = rows.as_mut() {
+                if words[0] != "ENDCHAR" {
+                    ls.push(words[0]);
+                    continue;
+                }
+            }
+            match words[0] {
+                "FONTBOUNDINGBOX" if words.len() >= 5 => {
+                    let vals = nums(&words[1..5])?;
+                    boundingbox = (vals[0], vals[1], vals[2], vals[3]);
+                },
+                "FONT_ASCENT" if words.len() >= 2 => {
+                    ascent = Some(nums(&words[1..2])?[0]);
+                },
+                "FONT_DESCENT" if words.len() >= 2 => {
+                    descent = nums(&words[1..2])?[0];
+                },
+                "STARTCHAR" => {
+                    encoding = -1;
+                    advance = boundingbox.0;
+                    bbx = boundingbox;
+                },
+                "ENCODING" if words.len() >= 2 => {
+                    encoding = nums(&words[1..2])?[0];
+                },
+                "DWIDTH" if words.len() >= 2 => {
+                    advance = nums(&words[1..2])?[0];
+                },
+                "BBX" if words.len() >= 5 => {
+                    let vals = nums(&words[1..5])?;
+                    bbx = (vals[0], vals[1], vals[2], vals[3]);
+                },
+                "BITMAP" => {
+                    rows = Some(Vec::new());
+                },
+                "ENDCHAR" => {
+                    let ls = rows.take().unwrap_or_default();
+                    let (width, height) = (bbx.0.max(0) as usize, bbx.1.max(0) as usize);
+                    let mut bits: Vec<bool> = Vec::with_capacity(width * height);
+                    for jx in 0..height {
+                        let row = ls.get(jx).copied().unwrap_or("");
+                        let rowbits: Vec<bool> = row.chars()
+                            .flat_map(|ch| {
+                                let val = ch.to_digit(16).unwrap_or(0);
+                                (0..4).map(move |bit| val & (0x8 >> bit) != 0)
+                            })
+                            .collect();
+                        for ix in 0..width {
+                            bits.push(rowbits.get(ix).copied().unwrap_or(false));
+                        }
+                    }
+                    let fontascent = ascent.unwrap_or(boundingbox.1 + boundingbox.3);
+                    let glyph = Glyph {
+                        width,
+                        height,
+                        xoff: bbx.2,
+                        yoff: fontascent - (bbx.3 + bbx.1),
+                        advance,
+                        bits,
+                    };
+                    if let Some(ch) = u32::try_from(encoding).ok().and_then(char::from_u32) {
+                        glyphs.insert(ch, glyph);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        if glyphs.is_empty() {
+            return Err("BDF file has no glyphs".to_string());
+        }
+        let lineheight = match ascent {
+            Some(val) => val + descent,
+            None => boundingbox.1,
+        };
+        Ok(Font { lineheight, glyphs })
+    }
+}
+
+impl Img<f32> {
+
+    pub fn draw_text(&self, text: &str, xpos: f32, ypos: f32, scale: f32, font: &Font, paint: &Paint) -> Img<f32> {
+        // Each font pixel becomes a SCALExSCALE square, with its edges
+        // antialiased. (XPOS, YPOS) is the top left of the first line.
+        let mut coverage: Vec<f32> = vec![0.0; self.pixcount()];
+
+        let mut addsquare = |x0: f32, y0: f32| {
+            let (x1, y1) = (x0 + scale, y0 + scale);
+            let ixmin = x0.floor().max(0.0) as usize;
+            let jxmin = y0.floor().max(0.0) as usize;
+            let ixmax = (x1.ceil().max(0.0) as usize).min(self.width);
+            let jxmax = (y1.ceil().max(0.0) as usize).min(self.height);
+            for jx in jxmin..jxmax {
+                let yover = y1.min(jx as f32 + 1.0) - y0.max(jx as f32);
+                for ix in ixmin..ixmax {
+                    let xover = x1.min(ix as f32 + 1.0) - x0.max(ix as f32);
+                    if xover > 0.0 && yover > 0.0 {
+                        coverage[jx * self.width + ix] += xover * yover;
+                    }
+                }
+            }
+        };
+
+        let fallback = font.glyphs.get(&'?');
+        let mut penx: i32 = 0;
+        let mut peny: i32 = 0;
+        for ch in text.chars() {
+            if ch == '\n' {
+                penx = 0;
+                peny += font.lineheight;
+                continue;
+            }
+            let glyph = match font.glyphs.get(&ch).or(fallback) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            for jx in 0..glyph.height {
+                for ix in 0..glyph.width {
+                    if glyph.bits[jx * glyph.width + ix] {
+                        let fx = (penx + glyph.xoff + ix as i32) as f32;
+                        let fy = (peny + glyph.yoff + jx as i32) as f32;
+                        addsquare(xpos + fx * scale, ypos + fy * scale);
+                    }
+                }
+            }
+            penx += glyph.advance;
+        }
+
+        self.paint_coverage(0, 0, self.width, &coverage, paint)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn psf1_truncated_header() {
+        let err = Font::parse_psf1(&[0x36, 0x04]).err();
+        assert_eq!(err.as_deref(), Some("PSF1 header is truncated"));
+    }
+}
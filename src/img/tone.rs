@@ -0,0 +1,196 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Tone adjustments: gamma, levels, curves and lookup tables. All of
+// these work on the 0..255 scale, one channel at a time.
+
+pub fn gamma_value(val: f32, gamma: f32) -> f32 {
+    // Gamma above 1 brightens the midtones.
+    255.0 * (val.max(0.0) / 255.0).powf(1.0 / gamma)
+}
+
+#[derive(Debug, Clone)]
+pub struct Levels {
+    pub inblack: Pix<f32>,
+    pub inwhite: Pix<f32>,
+    pub gamma: Pix<f32>,
+    pub outblack: Pix<f32>,
+    pub outwhite: Pix<f32>,
+}
+
+fn levels_value(val: f32, inblack: f32, inwhite: f32, gamma: f32, outblack: f32, outwhite: f32) -> f32 {
+    let frac = if inwhite > inblack {
+        ((val - inblack) / (inwhite - inblack)).clamp(0.0, 1.0)
+    }
+    else if val >= inwhite {
+        1.0
+    }
+    else {
+        0.0
+    };
+    outblack + frac.powf(1.0 / gamma) * (outwhite - outblack)
+}
+
+pub struct ToneCurve {
+    // A monotone cubic (Fritsch-Carlson) through the control points, so
+    // the curve never overshoots between them. Flat beyond the ends.
+    xs: Vec<f32>,
+    ys: Vec<f32>,
+    tangents: Vec<f32>,
+}
+
+impl ToneCurve {
+    pub fn new(pts: &[(f32, f32)]) -> Result<ToneCurve, String> {
+        if pts.len() < 2 {
+            return Err("curve needs at least two points".to_string());
+        }
+        for pair in pts.windows(2) {
+            if pair[1].0 <= pair[0].0 {
+                return Err(format!("curve points must increase in x: {} then {}", pair[0].0, pair[1].0));
+            }
+        }
+        let xs: Vec<f32> = pts.iter().map(|pt| pt.0).collect();
+        let ys: Vec<f32> = pts.iter().map(|pt| pt.1).collect();
+        let count = pts.len();
+
+        let secants: Vec<f32> = (0..count-1)
+            .map(|index| (ys[index+1] - ys[index]) / (xs[index+1] - xs[index]))
+            .collect();
+        let mut tangents: Vec<f32> = vec![0.0; count];
+        tangents[0] = secants[0];
+        tangents[count-1] = secants[count-2];
+        for index in 1..count-1 {
+            let (d0, d1) = (secants[index-1], secants[index]);
+            tangents[index] = if d0 * d1 <= 0.0 { 0.0 } else { (d0 + d1) * 0.5 };
+        }
+        for index in 0..count-1 {
+            let delta = secants[index];
+            if delta == 0.0 {
+                tangents[index] = 0.0;
+                tangents[index+1] = 0.0;
+                continue;
+            }
+            let alpha = tangents[index] / delta;
+            let beta = tangents[index+1] / delta;
+            let mag = alpha * alpha + beta * beta;
+            if mag > 9.0 {
+                let scale = 3.0 / mag.sqrt();
+                tangents[index] = scale * alpha * delta;
+                tangents[index+1] = scale * beta * delta;
+            }
+        }
+
+        Ok(ToneCurve { xs, ys, tangents })
+    }
+
+    pub fn at(&self, val: f32) -> f32 {
+        let last = self.xs.len() - 1;
+        if val <= self.xs[0] {
+            return self.ys[0];
+        }
+        if val >= self.xs[last] {
+            return self.ys[last];
+        }
+        let index = self.xs.partition_point(|xval| *xval <= val) - 1;
+        let width = self.xs[index+1] - self.xs[index];
+        let tval = (val - self.xs[index]) / width;
+        let (t2, t3) = (tval * tval, tval * tval * tval);
+        let h00 = 2.0*t3 - 3.0*t2 + 1.0;
+        let h10 = t3 - 2.0*t2 + tval;
+        let h01 = -2.0*t3 + 3.0*t2;
+        let h11 = t3 - t2;
+        h00 * self.ys[index] + h10 * width * self.tangents[index]
+            + h01 * self.ys[index+1] + h11 * width * self.tangents[index+1]
+    }
+}
+
+pub fn lut_value(table: &[f32], val: f32) -> f32 {
+    // The table entries are spread evenly over 0..255; interpolate
+    // linearly between them.
+    if table.len() == 1 {
+        return table[0];
+    }
+    let pos = (val / 255.0).clamp(0.0, 1.0) * (table.len() - 1) as f32;
+    let lo = pos.floor() as usize;
+    let hi = (lo + 1).min(table.len() - 1);
+    table[lo] + (table[hi] - table[lo]) * (pos - lo as f32)
+}
+
+impl Img<f32> {
+
+    pub fn levels(&self, lev: &Levels) -> Img<f32> {
+        self.map(|pix| Pix {
+            r: levels_value(pix.r, lev.inblack.r, lev.inwhite.r, lev.gamma.r, lev.outblack.r, lev.outwhite.r),
+            g: levels_value(pix.g, lev.inblack.g, lev.inwhite.g, lev.gamma.g, lev.outblack.g, lev.outwhite.g),
+            b: levels_value(pix.b, lev.inblack.b, lev.inwhite.b, lev.gamma.b, lev.outblack.b, lev.outwhite.b),
+        })
+    }
+
+    pub fn curves(&self, curves: &[ToneCurve; 3]) -> Img<f32> {
+        self.map(|pix| Pix {
+            r: curves[0].at(pix.r),
+            g: curves[1].at(pix.g),
+            b: curves[2].at(pix.b),
+        })
+    }
+
+    pub fn lut(&self, tables: &[Vec<f32>; 3]) -> Img<f32> {
+        self.map(|pix| Pix {
+            r: lut_value(&tables[0], pix.r),
+            g: lut_value(&tables[1], pix.g),
+            b: lut_value(&tables[2], pix.b),
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve_passes_through_points() {
+        let pts = [(0.0, 0.0), (64.0, 100.0), (128.0, 110.0), (255.0, 255.0)];
+        let curve = ToneCurve::new(&pts).unwrap();
+        for (xval, yval) in pts {
+            assert!((curve.at(xval) - yval).abs() < 1.0e-3, "at({}) = {}", xval, curve.at(xval));
+        }
+        assert_eq!(curve.at(-10.0), 0.0);
+        assert_eq!(curve.at(300.0), 255.0);
+    }
+
+    #[test]
+    fn curve_is_monotone() {
+        // A steep rise then a near-flat run would overshoot with a
+        // plain cubic spline.
+        let pts = [(0.0, 0.0), (64.0, 100.0), (128.0, 110.0), (192.0, 111.0), (255.0, 255.0)];
+        let curve = ToneCurve::new(&pts).unwrap();
+        let mut prev = curve.at(0.0);
+        for step in 1..=255 {
+            let val = curve.at(step as f32);
+            assert!(val >= prev - 1.0e-4, "curve falls at {}: {} after {}", step, val, prev);
+            prev = val;
+        }
+        for pair in pts.windows(2) {
+            for step in 0..=16 {
+                let xval = pair[0].0 + (pair[1].0 - pair[0].0) * step as f32 / 16.0;
+                let val = curve.at(xval);
+                assert!(val >= pair[0].1 - 1.0e-3 && val <= pair[1].1 + 1.0e-3, "overshoot at {}: {}", xval, val);
+            }
+        }
+    }
+
+    #[test]
+    fn curve_flat_segment_stays_flat() {
+        let curve = ToneCurve::new(&[(0.0, 50.0), (100.0, 50.0), (255.0, 200.0)]).unwrap();
+        for step in 0..=100 {
+            assert!((curve.at(step as f32) - 50.0).abs() < 1.0e-4);
+        }
+    }
+
+    #[test]
+    fn curve_rejects_bad_points() {
+        assert!(ToneCurve::new(&[(0.0, 0.0)]).is_err());
+        assert!(ToneCurve::new(&[(0.0, 0.0), (128.0, 10.0), (128.0, 20.0)]).is_err());
+    }
+}
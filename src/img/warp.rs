@@ -0,0 +1,271 @@
+use crate::img::pixel::Pix;
+use crate::img::Img;
+
+// Geometric warps by a 3x3 matrix, stored row-major as nine numbers.
+// The matrix maps source coordinates to destination coordinates:
+//
+//   x' = (m0 x + m1 y + m2) / (m6 x + m7 y + m8)
+//   y' = (m3 x + m4 y + m5) / (m6 x + m7 y + m8)
+//
+// An affine matrix has a bottom row of 0 0 1. Warping inverts the
+// matrix and samples the source for each destination pixel.
+
+#[derive(Debug, Clone, Copy)]
+pub enum Sampler {
+    Nearest,
+    Bilinear,
+    Bicubic,
+}
+
+impl Sampler {
+    pub fn from_name(name: &str) -> Option<Sampler> {
+        match name {
+            "nearest" => Some(Sampler::Nearest),
+            "bilinear" => Some(Sampler::Bilinear),
+            "bicubic" => Some(Sampler::Bicubic),
+            _ => None,
+        }
+    }
+}
+
+pub fn homography(src: &[(f32, f32)], dst: &[(f32, f32)]) -> Result<[f32; 9], String> {
+    // Solve for the matrix (with m8 = 1) that carries the four SRC
+    // points onto the four DST points. Each pair gives two linear
+    // equations in the other eight entries.
+    if src.len() != 4 || dst.len() != 4 {
+        return Err(format!("homography needs four points each, not {} and {}", src.len(), dst.len()));
+    }
+    let mut rows: Vec<[f64; 9]> = Vec::with_capacity(8);
+    for ((sx, sy), (dx, dy)) in src.iter().zip(dst) {
+        let (sx, sy, dx, dy) = (*sx as f64, *sy as f64, *dx as f64, *dy as f64);
+        rows.push([sx, sy, 1.0, 0.0, 0.0, 0.0, -dx * sx, -dx * sy, dx]);
+        rows.push([0.0, 0.0, 0.0, sx, sy, 1.0, -dy * sx, -dy * sy, dy]);
+    }
+
+    // Gaussian elimination with partial pivoting.
+    for col in 0..8 {
+        let pivot = (col..8)
+            .max_by(|r1, r2| rows[*r1][col].abs().total_cmp(&rows[*r2][col].abs()))
+            .unwrap_or(col);
+        if rows[pivot][col].abs() < 1.0e-10 {
+            return Err("homography points are degenerate (three in a line?)".to_string());
+        }
+        rows.swap(col, pivot);
+        for row in 0..8 {
+            if row == col {
+                continue;
+            }
+            let factor = rows[row][col] / rows[col][col];
+            if factor == 0.0 {
+                continue;
+            }
+            let pivotrow = rows[col];
+            for (val, pval) in rows[row].iter_mut().zip(&pivotrow).skip(col) {
+                *val -= factor * pval;
+            }
+        }
+    }
+
+    let mut res = [1.0; 9];
+    for (index, val) in res.iter_mut().take(8).enumerate() {
+        *val = (rows[index][8] / rows[index][index]) as f32;
+    }
+    Ok(res)
+}
+
+pub fn invert_matrix(mat: &[f32; 9]) -> Option<[f32; 9]> {
+    let m: Vec<f64> = mat.iter().map(|val| *val as f64).collect();
+    let cofactors = [
+        m[4] * m[8] - m[5] * m[7],
+        m[2] * m[7] - m[1] * m[8],
+        m[1] * m[5] - m[2] * m[4],
+        m[5] * m[6] - m[3] * m[8],
+        m[0] * m[8] - m[2] * m[6],
+        m[2] * m[3] - m[0] * m[5],
+        m[3] * m[7] - m[4] * m[6],
+        m[1] * m[6] - m[0] * m[7],
+        m[0] * m[4] - m[1] * m[3],
+    ];
+    let det = m[0] * cofactors[0] + m[1] * cofactors[3] + m[2] * cofactors[6];
+    if det.abs() < 1.0e-12 || !det.is_finite() {
+        return None;
+    }
+    Some(cofactors.map(|val| (val / det) as f32))
+}
+
+// Affine matrices are the top two rows, [a b c d e f]:
+//   x' = a x + b y + c,  y' = d x + e y + f
+
+pub const AFFINE_IDENTITY: [f32; 6] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+
+pub fn affine_apply(mat: &[f32; 6], xpos: f32, ypos: f32) -> (f32, f32) {
+    (mat[0] * xpos + mat[1] * ypos + mat[2], mat[3] * xpos + mat[4] * ypos + mat[5])
+}
+
+pub fn affine_concat(first: &[f32; 6], second: &[f32; 6]) -> [f32; 6] {
+    // The matrix that applies FIRST, then SECOND.
+    let [a1, b1, c1, d1, e1, f1] = *first;
+    let [a2, b2, c2, d2, e2, f2] = *second;
+    [
+        a2 * a1 + b2 * d1, a2 * b1 + b2 * e1, a2 * c1 + b2 * f1 + c2,
+        d2 * a1 + e2 * d1, d2 * b1 + e2 * e1, d2 * c1 + e2 * f1 + f2,
+    ]
+}
+
+pub fn affine_invert(mat: &[f32; 6]) -> Option<[f32; 6]> {
+    let [a, b, c, d, e, f] = *mat;
+    let det = a * e - b * d;
+    if det == 0.0 || !det.is_finite() {
+        return None;
+    }
+    Some([
+        e / det, -b / det, (b * f - c * e) / det,
+        -d / det, a / det, (c * d - a * f) / det,
+    ])
+}
+
+pub fn affine_to_full(mat: &[f32; 6]) -> [f32; 9] {
+    [mat[0], mat[1], mat[2], mat[3], mat[4], mat[5], 0.0, 0.0, 1.0]
+}
+
+fn cubic_weights(frac: f32) -> [f32; 4] {
+    // Catmull-Rom weights for the samples at -1, 0, 1, 2.
+    let (t2, t3) = (frac * frac, frac * frac * frac);
+    [
+        0.5 * (-t3 + 2.0 * t2 - frac),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + frac),
+        0.5 * (t3 - t2),
+    ]
+}
+
+impl Img<f32> {
+
+    pub fn at_nearest(&self, xpos: f32, ypos: f32) -> Pix<f32> {
+        if xpos.is_nan() || ypos.is_nan() {
+            return Pix::default();
+        }
+        self.at_clamped(xpos.round() as i32, ypos.round() as i32).clone()
+    }
+
+    pub fn at_cubic(&self, xpos: f32, ypos: f32) -> Pix<f32> {
+        // Catmull-Rom over the surrounding 4x4 pixels. This can
+        // overshoot a little at hard edges.
+        if xpos.is_nan() || ypos.is_nan() {
+            return Pix::default();
+        }
+        let (x0, y0) = (xpos.floor() as i32, ypos.floor() as i32);
+        let xweights = cubic_weights(xpos - xpos.floor());
+        let yweights = cubic_weights(ypos - ypos.floor());
+        let mut res: Pix<f32> = Pix::default();
+        for (dy, ywt) in yweights.iter().enumerate() {
+            for (dx, xwt) in xweights.iter().enumerate() {
+                let pix = self.at_clamped(x0 + dx as i32 - 1, y0 + dy as i32 - 1);
+                let wt = xwt * ywt;
+                res.r += pix.r * wt;
+                res.g += pix.g * wt;
+                res.b += pix.b * wt;
+            }
+        }
+        res
+    }
+
+    pub fn at_sampled(&self, xpos: f32, ypos: f32, sampler: Sampler) -> Pix<f32> {
+        match sampler {
+            Sampler::Nearest => self.at_nearest(xpos, ypos),
+            Sampler::Bilinear => self.at_lerp(xpos, ypos),
+            Sampler::Bicubic => self.at_cubic(xpos, ypos),
+        }
+    }
+
+    pub fn warp(&self, mat: &[f32; 9], width: usize, height: usize, sampler: Sampler) -> Result<Img<f32>, String> {
+        // Returns a WIDTHxHEIGHT image. Like project, samples off the
+        // edge of the source are clamped; destination pixels that map
+        // from behind the horizon are black.
+        let inv = invert_matrix(mat)
+            .ok_or_else(|| format!("warp matrix cannot be inverted: {:?}", mat))?;
+        let mut res = Img::new(width, height);
+        for jx in 0..height {
+            for ix in 0..width {
+                let (xval, yval) = (ix as f32, jx as f32);
+                // WVAL is 1/w at the source point. Keep only points on
+                // the same side of the horizon as the source origin,
+                // where w is m8.
+                let wval = inv[6] * xval + inv[7] * yval + inv[8];
+                if wval * mat[8].signum() <= 0.0 {
+                    continue;
+                }
+                let srcx = (inv[0] * xval + inv[1] * yval + inv[2]) / wval;
+                let srcy = (inv[3] * xval + inv[4] * yval + inv[5]) / wval;
+                res.set(ix, jx, self.at_sampled(srcx, srcy, sampler));
+            }
+        }
+        Ok(res)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: [(f32, f32); 4] = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+
+    fn apply(mat: &[f32; 9], (xpos, ypos): (f32, f32)) -> (f32, f32) {
+        let wval = mat[6] * xpos + mat[7] * ypos + mat[8];
+        ((mat[0] * xpos + mat[1] * ypos + mat[2]) / wval, (mat[3] * xpos + mat[4] * ypos + mat[5]) / wval)
+    }
+
+    fn assert_close(mat: &[f32], expect: &[f32]) {
+        for (val, want) in mat.iter().zip(expect) {
+            assert!((val - want).abs() < 1.0e-4, "{:?} vs {:?}", mat, expect);
+        }
+    }
+
+    #[test]
+    fn homography_identity() {
+        let mat = homography(&SQUARE, &SQUARE).unwrap();
+        assert_close(&mat, &affine_to_full(&AFFINE_IDENTITY));
+    }
+
+    #[test]
+    fn homography_translate_scale() {
+        let dst: Vec<(f32, f32)> = SQUARE.iter().map(|(xpos, ypos)| (2.0 * xpos + 5.0, 3.0 * ypos - 1.0)).collect();
+        let mat = homography(&SQUARE, &dst).unwrap();
+        assert_close(&mat, &[2.0, 0.0, 5.0, 0.0, 3.0, -1.0, 0.0, 0.0, 1.0]);
+        let inv = homography(&dst, &SQUARE).unwrap();
+        for pt in [(3.0, 4.0), (-2.5, 7.0)] {
+            let (xval, yval) = apply(&inv, apply(&mat, pt));
+            assert_close(&[xval, yval], &[pt.0, pt.1]);
+        }
+    }
+
+    #[test]
+    fn homography_perspective() {
+        let dst = [(1.0, 2.0), (9.0, 0.0), (12.0, 11.0), (-1.0, 8.0)];
+        let mat = homography(&SQUARE, &dst).unwrap();
+        for (src, want) in SQUARE.iter().zip(&dst) {
+            let (xval, yval) = apply(&mat, *src);
+            assert!((xval - want.0).abs() < 1.0e-3 && (yval - want.1).abs() < 1.0e-3);
+        }
+    }
+
+    #[test]
+    fn affine_concat_order() {
+        // Scale by 2, then shift by (1, 0): the shift is not scaled.
+        let mat = affine_concat(&[2.0, 0.0, 0.0, 0.0, 2.0, 0.0], &[1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+        assert_close(&mat, &[2.0, 0.0, 1.0, 0.0, 2.0, 0.0]);
+        let mat = [0.5, -1.5, 3.0, 2.0, 0.25, -4.0];
+        let inv = affine_invert(&mat).unwrap();
+        assert_close(&affine_concat(&mat, &inv), &AFFINE_IDENTITY);
+        assert!(affine_invert(&[1.0, 2.0, 0.0, 2.0, 4.0, 0.0]).is_none());
+    }
+
+    #[test]
+    fn homography_degenerate() {
+        let line = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 3.0)];
+        assert!(homography(&line, &SQUARE).is_err());
+        assert!(homography(&SQUARE, &line).is_err());
+        assert!(homography(&SQUARE[..3], &SQUARE[..3]).is_err());
+    }
+}
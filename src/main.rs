@@ -19,6 +19,12 @@ pub struct AppOptions {
 
     #[options(long="out", help = "output file")]
     outfiles: Vec<String>,
+
+    #[options(help = "random seed (default: seeded from entropy)")]
+    seed: Option<u64>,
+
+    #[options(help = "resample and blur in linear light")]
+    linear: bool,
 }
 
 fn main() {
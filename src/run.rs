@@ -7,11 +7,13 @@ use crate::args::Argument;
 use crate::script::Script;
 use crate::exec::ExecContext;
 
+#[allow(clippy::len_zero)]
 pub fn run(opts: &AppOptions) -> Result<(), Box<dyn Error>> {
 
     let args = parse_args(&opts.args)?;
     
-    let mut ctx = ExecContext::new();
+    let mut ctx = ExecContext::new(opts.seed);
+    ctx.set_linearlight(opts.linear);
 
     for arg in args {
         match arg {
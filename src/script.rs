@@ -11,10 +11,12 @@ pub enum ScriptToken {
     Delimiter(String), // parse only
     Name(String),
     StoreTo(String),
+    Bind(Vec<String>),
     String(String),
     Integer(i32),
     Float(f32),
     Size(i32, i32),
+    Point(f32, f32),
     Color(u8, u8, u8),
     Proc(Rc<Vec<ScriptToken>>),
 }
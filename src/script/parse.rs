@@ -36,25 +36,55 @@ fn parse_whitespace<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a s
 }
 
 fn parse_string<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, ScriptToken, E> {
-    combinator::map(
-        // for escaped chars, see https://github.com/rust-bakery/nom/blob/main/examples/string.rs
-        sequence::delimited(
-            character::complete::char('"'),
-            combinator::opt(bytes::complete::is_not("\"")),
-            character::complete::char('"')
-        ),
-        |res: Option<&str>| {
-            if let Some(val) = res {
-                ScriptToken::String(val.to_string())
-            }
-            else {
-                ScriptToken::String(String::default())
-            }
+    // Backslash escapes: \" \\ \n \t \r \0 \u{HEX}
+    let (mut pinput, _) = character::complete::char('"')(input)?;
+    let mut res = String::new();
+    loop {
+        let mut chars = pinput.chars();
+        match chars.next() {
+            None => {
+                return Err(Err::Failure(E::from_error_kind(pinput, ErrorKind::Fail)));
+            },
+            Some('"') => {
+                return Ok( (chars.as_str(), ScriptToken::String(res)) );
+            },
+            Some('\\') => {
+                let ch = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some('u') => {
+                        let (uinput, hex) = sequence::delimited(
+                            character::complete::char('{'),
+                            character::complete::hex_digit1,
+                            character::complete::char('}')
+                        )(chars.as_str())
+                            .map_err(|_err: Err<E>| {
+                                Err::Failure(E::from_error_kind(pinput, ErrorKind::Fail))
+                            })?;
+                        chars = uinput.chars();
+                        u32::from_str_radix(hex, 16).ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| Err::Failure(E::from_error_kind(pinput, ErrorKind::Fail)))?
+                    },
+                    _ => {
+                        return Err(Err::Failure(E::from_error_kind(pinput, ErrorKind::Fail)));
+                    },
+                };
+                res.push(ch);
+            },
+            Some(ch) => {
+                res.push(ch);
+            },
         }
-    )(input)
+        pinput = chars.as_str();
+    }
 }
 
-fn parse_tokterminator<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &str, E> {
+fn parse_tokterminator<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a str, E> {
     branch::alt((
         combinator::eof,
         character::complete::multispace1,
@@ -75,13 +105,37 @@ fn parse_name<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Sc
     )(input)
 }
 
-fn parse_operator<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, ScriptToken, E> {
+fn parse_litname<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, ScriptToken, E> {
+    // A literal name like /foo is pushed as the string "foo".
     combinator::map(
-        combinator::recognize(
-            multi::many1(
-                character::complete::one_of("+-*/<>%&|=!")
+        sequence::preceded(
+            character::complete::char('/'),
+            combinator::recognize(
+                sequence::tuple((
+                    bytes::complete::take_while1(|ch: char| ch == '_' || ch.is_ascii_alphabetic()),
+                    bytes::complete::take_while(|ch: char| ch == '_' || ch.is_ascii_alphanumeric()),
+                    combinator::peek(parse_tokterminator)
+                ))
             )
         ),
+        |val: &str| ScriptToken::String(val.to_string())
+    )(input)
+}
+
+fn parse_operator<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, ScriptToken, E> {
+    combinator::map(
+        branch::alt((
+            // A binder bar is always a token by itself, so || is two.
+            // Dict brackets stand alone too, so <</key is << /key.
+            bytes::complete::tag("|"),
+            bytes::complete::tag("<<"),
+            bytes::complete::tag(">>"),
+            combinator::recognize(
+                multi::many1(
+                    character::complete::one_of("+-*/<>%&=!")
+                )
+            ),
+        )),
         |val: &str| ScriptToken::Operator(val.to_string())
     )(input)
 }
@@ -95,6 +149,7 @@ fn parse_delimiter<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a st
     )(input)
 }
 
+#[allow(clippy::from_str_radix_10, clippy::needless_return)]
 fn parse_integer<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, ScriptToken, E> {
    let (pinput, pstr) = combinator::recognize(
        sequence::tuple((
@@ -121,6 +176,7 @@ fn parse_float<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, S
     )(input)
 }
 
+#[allow(clippy::from_str_radix_10, clippy::needless_return)]
 fn parse_size<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, ScriptToken, E> {
     let (pinput, (pstr1, pstr2)) =
     sequence::separated_pair(
@@ -151,6 +207,21 @@ fn parse_size<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Sc
     return Ok( (pinput, ScriptToken::Size(ival1, ival2)) );
 }
 
+fn parse_point<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, ScriptToken, E> {
+    // X,Y with no space; either part may be an integer or a float.
+    combinator::map(
+        sequence::terminated(
+            sequence::separated_pair(
+                nom::number::complete::float,
+                character::complete::char(','),
+                nom::number::complete::float
+            ),
+            combinator::peek(parse_tokterminator)
+        ),
+        |(xval, yval): (f32, f32)| ScriptToken::Point(xval, yval)
+    )(input)
+}
+
 fn parse_color<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, ScriptToken, E> {
     let (pinput, pstr) = combinator::recognize(
         sequence::tuple((
@@ -195,6 +266,7 @@ fn parse_anytoken<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str
         parse_integer,
         parse_float,
         parse_size,
+        parse_point,
         parse_color,
         parse_operator,
         parse_delimiter,
@@ -202,9 +274,37 @@ fn parse_anytoken<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str
 }
 
 fn parse_anytokenlist<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Vec<ScriptToken>, E> {
-    multi::many0(
-        parse_anytoken
-    )(input)
+    // Like many0(parse_anytoken), except that a /name literal is only
+    // recognized at the start of a token. After a name or number, the
+    // slash is division: b/c is "b / c".
+    let mut res: Vec<ScriptToken> = Vec::new();
+    let mut pinput = input;
+    loop {
+        let atstart = matches!(res.last(),
+            None | Some(ScriptToken::Whitespace) | Some(ScriptToken::Comment)
+            | Some(ScriptToken::Operator(_)) | Some(ScriptToken::Delimiter(_)));
+        let parsed = if atstart {
+            branch::alt((parse_litname, parse_anytoken))(pinput)
+        }
+        else {
+            parse_anytoken(pinput)
+        };
+        match parsed {
+            Ok((rest, tok)) => {
+                if rest.len() == pinput.len() {
+                    return Ok((pinput, res));
+                }
+                res.push(tok);
+                pinput = rest;
+            },
+            Err(Err::Error(_)) => {
+                return Ok((pinput, res));
+            },
+            Err(err) => {
+                return Err(err);
+            },
+        }
+    }
 }
 
 fn parse_with_termination<'a, R, F, E: ParseError<&'a str>>(input: &'a str, func: F) -> IResult<&'a str, R, E>
@@ -219,6 +319,7 @@ pub fn load_script_text(body: &str) -> Result<Script, String> {
     load_script(body, "<ARG>")
 }
 
+#[allow(clippy::to_string_in_format_args)]
 pub fn load_script_file(filename: &str) -> Result<Script, String> {
     let body = std::fs::read_to_string(filename)
         .map_err(|err| {
@@ -231,6 +332,7 @@ pub fn load_script_file(filename: &str) -> Result<Script, String> {
 fn load_script(body: &str, source: &str) -> Result<Script, String> {
     // parser returns Result<(&str, Vec<ScriptToken>), nom::Err<VerboseError<&str>>>
     
+    #[allow(clippy::needless_borrow)]
     let (_, rawtokens): (_, Vec<ScriptToken>) = parse_with_termination::<_, _, VerboseError<&str>>(&body, parse_anytokenlist)
         .map_err(|err| {
             match err {
@@ -250,19 +352,37 @@ fn load_script(body: &str, source: &str) -> Result<Script, String> {
 
     let mut tokens: Vec<ScriptToken> = Vec::new();
     let mut wasarrow = false;
+    let mut dictdepth: usize = 0;
+    let mut rawiter = rawtokens.into_iter().peekable();
 
-    for tok in rawtokens {    // consume original
+    while let Some(tok) = rawiter.next() {    // consume original
         match tok {
             ScriptToken::Whitespace => {},
             ScriptToken::Comment => {},
             ScriptToken::Operator(val) => {
-                if val == ">>".to_string() {
-                    wasarrow = true;
-                }
-                else if wasarrow {
+                if wasarrow {
                     return Err(format!("{}: arrow needs name, found {:?}", source, val));
                 }
+                if val == ">>" {
+                    // Inside a << dict literal, >> closes the dict and
+                    // must not touch a name (>>begin would read either
+                    // way). Elsewhere >> stores to the name after it,
+                    // with or without a space.
+                    if dictdepth > 0 {
+                        if let Some(ScriptToken::Name(name)) = rawiter.peek() {
+                            return Err(format!("{}: >>{} inside a dict literal is ambiguous; put a space after >> to close the dict", source, name));
+                        }
+                        dictdepth -= 1;
+                        tokens.push(ScriptToken::Name(val));
+                    }
+                    else {
+                        wasarrow = true;
+                    }
+                }
                 else {
+                    if val == "<<" {
+                        dictdepth += 1;
+                    }
                     tokens.push(ScriptToken::Name(val));
                 }
             }
@@ -287,9 +407,18 @@ fn load_script(body: &str, source: &str) -> Result<Script, String> {
         return Err(format!("{}: arrow needs name", source));
     }
 
+    #[allow(clippy::useless_format)]
     fn buildwrap(iter: &mut std::vec::IntoIter<ScriptToken>, istop: bool) -> Result<Rc<Vec<ScriptToken>>, String> {
         let mut ls: Vec<ScriptToken> = Vec::new();
         while let Some(tok) = iter.next() {
+            // A |name name...| binder is only meaningful as the first
+            // token of a script or proc.
+            if let ScriptToken::Name(ref val) = tok {
+                if val == "|" && ls.is_empty() {
+                    ls.push(buildbinder(iter)?);
+                    continue;
+                }
+            }
             if let ScriptToken::Delimiter(delim) = tok {
                 if delim == "}" {
                     if istop {
@@ -317,11 +446,29 @@ fn load_script(body: &str, source: &str) -> Result<Script, String> {
         }
         Ok(Rc::new(ls))
     }
+    fn buildbinder(iter: &mut std::vec::IntoIter<ScriptToken>) -> Result<ScriptToken, String> {
+        let mut names: Vec<String> = Vec::new();
+        for tok in iter.by_ref() {
+            match tok {
+                ScriptToken::Name(val) => {
+                    if val == "|" {
+                        return Ok(ScriptToken::Bind(names));
+                    }
+                    names.push(val);
+                },
+                other => {
+                    return Err(format!("binder needs name, found {:?}", other));
+                },
+            }
+        }
+        Err("unclosed binder".to_string())
+    }
     let wrappedtokens = buildwrap(&mut tokens.into_iter(), true)?; // consume original
 
     Ok(Script::new(source, wrappedtokens))
 }
 
+#[allow(clippy::needless_borrow)]
 pub fn match_color(body: &str) -> Option<(u8, u8, u8)>
 {
     if let Ok((_, ScriptToken::Color(rval, gval, bval))) = parse_with_termination::<_, _, ()>(&body, parse_color) {
@@ -332,6 +479,7 @@ pub fn match_color(body: &str) -> Option<(u8, u8, u8)>
     }
 }
 
+#[allow(clippy::needless_borrow)]
 pub fn match_size(body: &str) -> Option<(i32, i32)>
 {
     if let Ok((_, ScriptToken::Size(width, height))) = parse_with_termination::<_, _, ()>(&body, parse_size) {
@@ -342,3 +490,51 @@ pub fn match_size(body: &str) -> Option<(i32, i32)>
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(body: &str) -> Vec<String> {
+        let script = load_script_text(body).unwrap();
+        script.tokens().iter().map(|tok| format!("{:?}", tok)).collect()
+    }
+
+    #[test]
+    fn litname_at_token_start() {
+        assert_eq!(lex("/foo"), vec!["String(\"foo\")"]);
+        assert_eq!(lex("[/a /b]"), vec!["Name(\"[\")", "String(\"a\")", "String(\"b\")", "Name(\"]\")"]);
+    }
+
+    #[test]
+    fn slash_after_word_divides() {
+        assert_eq!(lex("b/c"), vec!["Name(\"b\")", "Name(\"/\")", "Name(\"c\")"]);
+        assert_eq!(lex("2/c"), vec!["Integer(2)", "Name(\"/\")", "Name(\"c\")"]);
+        assert_eq!(lex("b /c"), vec!["Name(\"b\")", "String(\"c\")"]);
+    }
+
+    #[test]
+    fn bars_are_separate_tokens() {
+        assert_eq!(lex("1 ||"), vec!["Integer(1)", "Name(\"|\")", "Name(\"|\")"]);
+        assert_eq!(lex("|| 1"), vec!["Bind([])", "Integer(1)"]);
+        assert_eq!(lex("|a b| a"), vec!["Bind([\"a\", \"b\"])", "Name(\"a\")"]);
+    }
+
+    #[test]
+    fn arrow_stores() {
+        assert_eq!(lex("1 >>name"), vec!["Integer(1)", "StoreTo(\"name\")"]);
+        assert_eq!(lex("1 >> name"), vec!["Integer(1)", "StoreTo(\"name\")"]);
+        assert!(load_script_text("1 >>").is_err());
+        assert!(load_script_text("1 >> 2").is_err());
+    }
+
+    #[test]
+    fn dict_brackets() {
+        let dict = vec!["Name(\"<<\")", "String(\"k\")", "Integer(1)", "Name(\">>\")"];
+        assert_eq!(lex("<< /k 1 >>"), dict);
+        assert_eq!(lex("<</k 1>>"), dict);
+        assert_eq!(lex("<< /k 1 >> begin"), [dict.clone(), vec!["Name(\"begin\")"]].concat());
+        assert_eq!(lex("<< /k 1 >> >>opts"), [dict, vec!["StoreTo(\"opts\")"]].concat());
+        assert!(load_script_text("<< /k 1 >>begin").is_err());
+    }
+}